@@ -0,0 +1,111 @@
+use anyhow::Result;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const BACKUP_PREFIX: &str = "settings.json.bak.";
+
+pub const DEFAULT_BACKUP_COUNT: usize = 5;
+
+#[derive(Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub timestamp: String,
+}
+
+// Used unless --backup-dir or CCD_BACKUP_DIR points somewhere else
+pub fn default_backup_dir(claude_dir: &Path) -> PathBuf {
+    claude_dir.join("backups")
+}
+
+// Creates the directory if needed and checks a file can be written there, so a switch fails
+// before settings.json is touched rather than halfway through
+pub fn ensure_backup_dir(backup_dir: &Path) -> Result<()> {
+    if let Err(e) = fs::create_dir_all(backup_dir) {
+        anyhow::bail!("Could not create backup directory {}: {}", backup_dir.display(), e);
+    }
+    let probe = backup_dir.join(format!(".ccd-write-test.{}", std::process::id()));
+    if let Err(e) = fs::write(&probe, b"") {
+        anyhow::bail!("Backup directory {} is not writable: {}", backup_dir.display(), e);
+    }
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+// Copies settings.json into the backup directory and prunes all but the `keep` newest backups
+pub fn create_backup(settings_path: &Path, backup_dir: &Path, keep: usize) -> Result<PathBuf> {
+    fs::create_dir_all(backup_dir)?;
+
+    let backup_path = backup_dir.join(format!("{}{}", BACKUP_PREFIX, current_timestamp()));
+    fs::copy(settings_path, &backup_path)?;
+    log::info!("Backed up settings.json to {}", backup_path.display());
+
+    prune_backups(backup_dir, keep)?;
+    Ok(backup_path)
+}
+
+// Newest first, ordered by the timestamp embedded in the file name rather than mtime
+pub fn list_backups(backup_dir: &Path) -> Result<Vec<Backup>> {
+    let mut backups = Vec::new();
+
+    if !backup_dir.exists() {
+        return Ok(backups);
+    }
+
+    for entry in fs::read_dir(backup_dir)? {
+        let path = entry?.path();
+        let timestamp = path.file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(BACKUP_PREFIX))
+            .map(|t| t.to_string());
+
+        if let Some(timestamp) = timestamp {
+            backups.push(Backup { path, timestamp });
+        }
+    }
+
+    backups.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    Ok(backups)
+}
+
+fn prune_backups(backup_dir: &Path, keep: usize) -> Result<()> {
+    for backup in list_backups(backup_dir)?.into_iter().skip(keep) {
+        fs::remove_file(&backup.path)?;
+    }
+    Ok(())
+}
+
+// UTC timestamp like 20250101T120000.123Z; fixed width so it sorts lexicographically
+fn current_timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time_of_day = secs % 86_400;
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        (time_of_day % 3600) / 60,
+        time_of_day % 60,
+        now.subsec_millis()
+    )
+}
+
+// Howard Hinnant's days-to-civil conversion for the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
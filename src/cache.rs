@@ -0,0 +1,62 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+
+// Parsed profile files keyed by path. An entry is reused while the file's mtime and size are
+// unchanged, so redrawing the selector only costs a stat per file instead of a read and parse.
+static CACHE: LazyLock<Mutex<HashMap<PathBuf, CachedFile>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+struct CachedFile {
+    modified: SystemTime,
+    len: u64,
+    parsed: Result<serde_json::Value, String>,
+}
+
+// The parsed JSON of a profile, or a description of why it can't be read or parsed
+pub fn parse_profile(path: &Path) -> Result<serde_json::Value, String> {
+    // An entry of profiles.json is cached as part of the whole file
+    if let Some((file, name)) = crate::config::profile_entry(path) {
+        return parse_profile(&file)
+            .and_then(|profiles| crate::config::profiles_file_entry(&profiles, name, &file).map_err(|e| e.to_string()));
+    }
+
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    let modified = metadata.modified().map_err(|e| e.to_string())?;
+    let len = metadata.len();
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(entry) = cache.get(path) {
+        if entry.modified == modified && entry.len == len {
+            return entry.parsed.clone();
+        }
+    }
+
+    log::debug!("Parsing {}", path.display());
+    let parsed = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| crate::config::parse_profile_str(path, &content).map_err(|e| e.to_string()));
+    cache.insert(path.to_path_buf(), CachedFile { modified, len, parsed: parsed.clone() });
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reparses_after_the_file_changes() {
+        let path = std::env::temp_dir().join(format!("ccd-cache-test-{}-settings.json", std::process::id()));
+        fs::write(&path, r#"{"env":{}}"#).unwrap();
+        assert_eq!(parse_profile(&path).unwrap(), serde_json::json!({ "env": {} }));
+
+        fs::write(&path, "{broken").unwrap();
+        assert!(parse_profile(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+        assert!(parse_profile(&path).is_err());
+    }
+}
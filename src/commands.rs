@@ -1,23 +1,76 @@
 use anyhow::Result;
 use crossterm::{
-    cursor::Show,
     event::{self, Event},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, LeaveAlternateScreen},
+    style::Stylize,
+    terminal::{disable_raw_mode, enable_raw_mode},
 };
-use dirs::home_dir;
 use std::{
     collections::HashMap,
     env, fs,
-    io::{self},
-    path::PathBuf,
-    process::Stdio,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    time::Duration,
 };
-use tokio::process::Command as TokioCommand;
+use tokio::{
+    net::TcpStream,
+    process::{Child, Command as TokioCommand},
+    sync::watch,
+};
+
+use crate::config::{ClaudeCodeRouterConfig, ClaudeSettings, ConfigDirs, ConfigItem, ConfigType, EnvMode, SortOrder};
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Debug, Clone)]
+pub struct SwitchOptions {
+    pub dirs: ConfigDirs,
+    pub link: bool,
+    pub dry_run: bool,
+    pub confirm: bool,
+    pub keep_local: bool,
+    pub wait: bool,
+    pub hooks: bool,
+    // False for `switch`, which activates the profile and returns without starting claude
+    pub launch: bool,
+    // --watch: relaunch claude whenever the profile file changes
+    pub watch: bool,
+    // Set while watching; a new value means the profile changed and claude should be stopped
+    pub reload: Option<watch::Receiver<u64>>,
+    pub force: bool,
+    pub show_secrets: bool,
+    pub color: bool,
+    pub alt_screen: bool,
+    pub backup_count: usize,
+    // --backup-dir, then CCD_BACKUP_DIR, then ~/.claude/backups
+    pub backup_dir: PathBuf,
+    pub sort: SortOrder,
+    pub env_mode: EnvMode,
+    pub keep_anthropic_env: bool,
+    pub type_filter: Option<ConfigType>,
+    pub ccr_timeout: Duration,
+    // Command used for ccr restart/status/stop, "ccr" unless --ccr-bin or CCD_CCR_BIN is set
+    pub ccr_bin: String,
+    pub ccr_restart: bool,
+    pub ccr_stop: CcrStop,
+    // --launch-cmd: a command line run in place of claude, with the same environment
+    pub launch_cmd: Option<String>,
+    pub claude_args: Vec<String>,
+}
 
-use crate::config::{ConfigItem, ConfigType};
+// When to run `ccr stop` after claude exits
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum CcrStop {
+    // Only if this run started or restarted CCR; one that was already running may be shared
+    // with claude in another terminal
+    #[default]
+    IfStarted,
+    Always,
+    Never,
+}
 
-pub async fn launch_with_config_path(config_path: &str) -> Result<()> {
+// Returns claude's exit code
+pub async fn launch_with_config_path(config_path: &str, options: &SwitchOptions) -> Result<i32> {
     let path = PathBuf::from(config_path);
 
     if !path.exists() {
@@ -28,10 +81,10 @@ pub async fn launch_with_config_path(config_path: &str) -> Result<()> {
         .and_then(|n| n.to_str())
         .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?;
 
-    let (name, config_type) = if file_name.ends_with("-settings.json") {
-        (file_name.strip_suffix("-settings.json").unwrap().to_string(), ConfigType::Claude)
-    } else if file_name.ends_with("-config.json") {
-        (file_name.strip_suffix("-config.json").unwrap().to_string(), ConfigType::CodeRouter)
+    let (name, config_type) = if let Some(name) = crate::config::strip_profile_suffix(file_name, "-settings") {
+        (name.to_string(), ConfigType::Claude)
+    } else if let Some(name) = crate::config::strip_profile_suffix(file_name, "-config") {
+        (name.to_string(), ConfigType::CodeRouter)
     } else if file_name == "config.json" {
         ("config".to_string(), ConfigType::CodeRouter)
     } else {
@@ -42,210 +95,1580 @@ pub async fn launch_with_config_path(config_path: &str) -> Result<()> {
         name,
         path,
         config_type,
+        active: false,
+        loaded: false,
+        pinned: false,
+        description: None,
+        error: None,
+        modified: None,
     };
 
-    switch_configuration(&config_item).await
+    run_configuration(&config_item, options).await
 }
 
-fn cleanup_local_settings() -> Result<()> {
-    let current_dir = std::env::current_dir()?;
-    let local_settings_path = current_dir.join(".claude").join("settings.local.json");
-    
-    if local_settings_path.exists() {
+pub async fn launch_by_name(name: &str, options: &SwitchOptions) -> Result<i32> {
+    let config_item = crate::config::find_configuration(&options.dirs, name)?;
+    run_configuration(&config_item, options).await
+}
+
+// `code --last`: unlike the default, a missing or deleted profile is an error, not the selector
+pub fn last_configuration(dirs: &ConfigDirs) -> Result<ConfigItem> {
+    let Some(name) = crate::state::State::load(dirs).last_used else {
+        anyhow::bail!("No configuration has been used yet, run `claude-codust code <name>` first");
+    };
+    let configs = crate::config::load_configurations(dirs, SortOrder::default())?;
+    match configs.into_iter().find(|c| c.name == name) {
+        Some(config) => Ok(config),
+        None => anyhow::bail!("The last used configuration '{}' no longer exists", name),
+    }
+}
+
+// The default profile for `code` without a name, if one is set and still exists
+pub fn default_configuration(dirs: &ConfigDirs) -> Option<ConfigItem> {
+    let name = crate::state::State::load(dirs).default?;
+    match crate::config::find_configuration(dirs, &name) {
+        Ok(config) => Some(config),
+        Err(_) => {
+            log::warn!("Default configuration '{}' no longer exists, showing the selector", name);
+            None
+        }
+    }
+}
+
+// Sets, clears or (with neither) prints the default profile
+pub fn default_profile(dirs: &ConfigDirs, name: Option<&str>, clear: bool) -> Result<()> {
+    let mut state = crate::state::State::load(dirs);
+
+    if clear {
+        state.default = None;
+        state.save(dirs)?;
+        status!("Cleared the default configuration");
+    } else if let Some(name) = name {
+        let config = crate::config::find_configuration(dirs, name)?;
+        state.default = Some(config.name.clone());
+        state.save(dirs)?;
+        status!("Default configuration is now {}", config.name);
+    } else {
+        match state.default {
+            Some(default) => println!("{}", default),
+            None => status!("No default configuration set"),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn list_configurations(dirs: &ConfigDirs, json: bool, sort: SortOrder, type_filter: Option<ConfigType>) -> Result<()> {
+    let mut configs = crate::config::load_configurations(dirs, sort)?;
+    if let Some(config_type) = type_filter {
+        configs.retain(|c| c.config_type == config_type);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&configs)?);
+    } else {
+        for config in &configs {
+            println!("{}\t{}\t{}", config.name, config.config_type.as_str(), config.path.display());
+        }
+    }
+
+    Ok(())
+}
+
+// Prints the name of the live profile for use in shell prompts. When both a Claude and a
+// CodeRouter profile are active, the last used one is preferred. Prints nothing when unknown.
+pub fn print_current(dirs: &ConfigDirs, json: bool) -> Result<()> {
+    let configs = crate::config::load_configurations(dirs, SortOrder::default())?;
+    let last_used = crate::state::State::load(dirs).last_used;
+
+    let mut active = configs.iter().filter(|c| c.active);
+    let current = active.clone()
+        .find(|c| last_used.as_deref() == Some(c.name.as_str()))
+        .or_else(|| active.clone().find(|c| c.loaded))
+        .or_else(|| active.next());
+
+    // On stderr, so a shell prompt using the name is unaffected
+    if let Some(note) = crate::config::unmatched_router_config(dirs, &configs) {
+        log::warn!("{}", note);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&current)?);
+    } else if let Some(config) = current {
+        println!("{}", config.name);
+    }
+
+    Ok(())
+}
+
+pub fn create_profile(dirs: &ConfigDirs, ccr: bool, force: bool) -> Result<()> {
+    let name = prompt("Profile name")?;
+    // Hidden files are never scanned, so a leading dot would make the profile unreachable
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        anyhow::bail!("Invalid profile name: '{}'", name);
+    }
+
+    let path = if ccr {
+        dirs.router.join(format!("{}-config.json", name))
+    } else {
+        dirs.claude.join(format!("{}-settings.json", name))
+    };
+
+    if path.exists() && !force {
+        anyhow::bail!("{} already exists, use --force to overwrite it", path.display());
+    }
+
+    let description = prompt("Description (optional)")?;
+
+    let content = if ccr {
+        let port = prompt("Port [3456]")?;
+        let api_key = prompt("API key (leave empty for none)")?;
+
+        let mut config = HashMap::new();
+        let port = if port.is_empty() { "3456".to_string() } else { port };
+        config.insert("PORT".to_string(), serde_json::Value::String(port));
+        if !api_key.is_empty() {
+            config.insert("APIKEY".to_string(), serde_json::Value::String(api_key));
+        }
+        if !description.is_empty() {
+            config.insert("description".to_string(), serde_json::Value::String(description));
+        }
+
+        serde_json::to_string_pretty(&ClaudeCodeRouterConfig { config })?
+    } else {
+        let base_url = prompt("Base URL (ANTHROPIC_BASE_URL)")?;
+        let token = prompt("API token (ANTHROPIC_AUTH_TOKEN)")?;
+
+        let mut env_obj = serde_json::Map::new();
+        if !base_url.is_empty() {
+            env_obj.insert("ANTHROPIC_BASE_URL".to_string(), serde_json::Value::String(base_url));
+        }
+        if !token.is_empty() {
+            env_obj.insert("ANTHROPIC_AUTH_TOKEN".to_string(), serde_json::Value::String(token));
+        }
+
+        let mut settings = HashMap::new();
+        settings.insert("env".to_string(), serde_json::Value::Object(env_obj));
+        if !description.is_empty() {
+            settings.insert("description".to_string(), serde_json::Value::String(description));
+        }
+
+        serde_json::to_string_pretty(&ClaudeSettings { settings })?
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    crate::config::write_atomic(&path, content.as_bytes())?;
+    status!("Created {}", path.display());
+
+    Ok(())
+}
+
+pub fn delete_profile(dirs: &ConfigDirs, name: &str, yes: bool, force: bool) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, name)?;
+
+    if config.active && !force {
+        anyhow::bail!("Configuration '{}' is currently active, use --force to delete it anyway", config.name);
+    }
+
+    println!("{}", config.path.display());
+    if !yes && !confirm("Delete this configuration?")? {
+        status!("Cancelled");
+        return Ok(());
+    }
+
+    match crate::config::profile_entry(&config.path) {
+        Some((file, entry)) => {
+            let mut profiles = crate::config::read_profile_raw(&file)?;
+            if let Some(obj) = profiles.as_object_mut() {
+                obj.shift_remove(entry);
+            }
+            crate::config::write_profile(&file, &profiles)?;
+        }
+        None => fs::remove_file(&config.path)?,
+    }
+    status!("Deleted {}", config.name);
+
+    Ok(())
+}
+
+pub fn restore_backup(dirs: &ConfigDirs, backup_dir: &Path, alt_screen: bool) -> Result<()> {
+    let claude_dir = &dirs.claude;
+    let backups = crate::backup::list_backups(backup_dir)?;
+
+    if backups.is_empty() {
+        println!("No settings.json backups found in {}", backup_dir.display());
+        return Ok(());
+    }
+
+    let items = backups.iter()
+        .map(|b| b.timestamp.clone())
+        .collect::<Vec<_>>();
+    let Some(index) = crate::ui::select_item("Restore settings.json from backup", &items, alt_screen)? else {
+        status!("Cancelled");
+        return Ok(());
+    };
+
+    let backup = &backups[index];
+    let settings_path = claude_dir.join("settings.json");
+    if !confirm(&format!("Overwrite {} with the backup from {}?", settings_path.display(), backup.timestamp))? {
+        status!("Cancelled");
+        return Ok(());
+    }
+
+    crate::config::write_atomic(&settings_path, &fs::read(&backup.path)?)?;
+    status!("Restored settings.json from backup {}", backup.timestamp);
+
+    Ok(())
+}
+
+// Copies a profile to a new name of the same type; never overwrites
+pub fn duplicate_profile(dirs: &ConfigDirs, source: &str, destination: &str) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, source)?;
+    let path = with_same_extension(crate::config::profile_path(dirs, config.config_type, destination)?, &config.path);
+
+    if path.exists() {
+        anyhow::bail!("{} already exists", path.display());
+    }
+    ensure_name_is_free(dirs, &configuration_name(config.config_type, destination))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    copy_profile(&config.path, &path)?;
+    status!("Copied {} to {}", config.name, path.display());
+
+    Ok(())
+}
+
+// The name a profile of `config_type` saved as `name` is listed under
+fn configuration_name(config_type: ConfigType, name: &str) -> String {
+    match config_type {
+        ConfigType::Claude => name.to_string(),
+        ConfigType::CodeRouter => format!("{}-ccr", name.strip_suffix("-ccr").unwrap_or(name)),
+    }
+}
+
+// A profile in another format or another directory can already use the name even though the
+// new file's path is free; the two would clash and one of them would be hidden
+fn ensure_name_is_free(dirs: &ConfigDirs, name: &str) -> Result<()> {
+    if let Ok(existing) = crate::config::find_configuration(dirs, name) {
+        anyhow::bail!("A configuration named '{}' already exists: {}", name, existing.path.display());
+    }
+    Ok(())
+}
+
+// Pinned profiles are listed first in the selector and `list`
+pub fn pin_profile(dirs: &ConfigDirs, name: &str, pin: bool) -> Result<()> {
+    let mut state = crate::state::State::load(dirs);
+    let is_pinned = state.pinned.iter().any(|p| p == name);
+
+    if pin {
+        // Unpinning needs no check, so names of deleted profiles can still be removed
+        let config = crate::config::find_configuration(dirs, name)?;
+        if is_pinned {
+            status!("{} is already pinned", config.name);
+            return Ok(());
+        }
+        state.pinned.push(config.name.clone());
+    } else {
+        if !is_pinned {
+            status!("{} is not pinned", name);
+            return Ok(());
+        }
+        state.pinned.retain(|p| p != name);
+    }
+
+    state.save(dirs)?;
+    status!("{} {}", if pin { "Pinned" } else { "Unpinned" }, name);
+    Ok(())
+}
+
+// Copies an external file into the profile directory for its type. Without `--type` the type
+// and name are guessed from the file name, e.g. `gemini-config.json` is a CCR profile `gemini`.
+pub fn import_profile(
+    dirs: &ConfigDirs,
+    file: &str,
+    name: Option<&str>,
+    config_type: Option<ConfigType>,
+    force: bool,
+) -> Result<()> {
+    let source = PathBuf::from(file);
+    let value = crate::config::read_profile(&source)
+        .map_err(|e| anyhow::anyhow!("Cannot import {}: {}", source.display(), e))?;
+
+    let file_name = source.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let guessed_router = crate::config::strip_profile_suffix(file_name, "-config");
+    let config_type = config_type.unwrap_or(if guessed_router.is_some() {
+        ConfigType::CodeRouter
+    } else {
+        ConfigType::Claude
+    });
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => guessed_router
+            .or_else(|| crate::config::strip_profile_suffix(file_name, "-settings"))
+            .or_else(|| source.file_stem().and_then(|s| s.to_str()))
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    if config_type == ConfigType::CodeRouter && value.get("PORT").is_none() && value.get("APIKEY").is_none() {
+        anyhow::bail!("{} has neither PORT nor APIKEY, it does not look like a CCR configuration", source.display());
+    }
+
+    let path = with_same_extension(crate::config::profile_path(dirs, config_type, &name)?, &source);
+    // --force only replaces this very file, never a profile of the same name in another format
+    if path.exists() {
+        if !force {
+            anyhow::bail!("{} already exists, use --force to overwrite it", path.display());
+        }
+    } else {
+        ensure_name_is_free(dirs, &configuration_name(config_type, &name))?;
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(&source, &path)?;
+    status!("Imported {} to {}", source.display(), path.display());
+
+    Ok(())
+}
+
+// With `redact` the profile is written through mask_secrets, in the format of the destination's extension
+pub fn export_profile(dirs: &ConfigDirs, name: &str, to: &str, redact: bool, force: bool) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, name)?;
+    let destination = PathBuf::from(to);
+
+    if destination.exists() && !force {
+        anyhow::bail!("{} already exists, use --force to overwrite it", destination.display());
+    }
+
+    if redact {
+        let value = crate::config::read_profile(&config.path)?;
+        crate::config::write_profile(&destination, &crate::config::mask_secrets(&value))?;
+    } else {
+        copy_profile(&config.path, &destination)?;
+    }
+    status!("Exported {} to {}", config.name, destination.display());
+
+    Ok(())
+}
+
+// An entry of profiles.json becomes a file of its own; other profiles are copied byte for byte
+fn copy_profile(source: &Path, destination: &Path) -> Result<()> {
+    if crate::config::profile_entry(source).is_some() {
+        crate::config::write_profile(destination, &crate::config::read_profile_raw(source)?)
+    } else {
+        fs::copy(source, destination)?;
+        Ok(())
+    }
+}
+
+// Keeps a YAML or TOML profile in its format when copying or renaming it
+fn with_same_extension(path: PathBuf, source: &Path) -> PathBuf {
+    if crate::config::profile_entry(source).is_some() {
+        return path;
+    }
+    match source.extension() {
+        Some(extension) => path.with_extension(extension),
+        None => path,
+    }
+}
+
+// Renames the profile file within its type; the remembered last-used name follows along
+pub fn rename_profile(dirs: &ConfigDirs, old: &str, new: &str) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, old)?;
+    if crate::config::profile_entry(&config.path).is_some() {
+        anyhow::bail!("{} is kept in {}, rename it there", config.name, crate::config::profile_file(&config.path).display());
+    }
+    let path = with_same_extension(crate::config::profile_path(dirs, config.config_type, new)?, &config.path);
+
+    if path.exists() {
+        anyhow::bail!("{} already exists", path.display());
+    }
+    let new_name = configuration_name(config.config_type, new);
+    ensure_name_is_free(dirs, &new_name)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&config.path, &path)?;
+
+    let mut state = crate::state::State::load(dirs);
+    if state.last_used.as_deref() == Some(config.name.as_str()) {
+        state.last_used = Some(new_name.clone());
+    }
+    if state.default.as_deref() == Some(config.name.as_str()) {
+        state.default = Some(new_name.clone());
+    }
+    if state.selected.as_deref() == Some(config.name.as_str()) {
+        state.selected = Some(new_name.clone());
+    }
+    if state.router_profile.as_deref() == Some(config.name.as_str()) {
+        state.router_profile = Some(new_name.clone());
+    }
+    for pinned in state.pinned.iter_mut().filter(|p| **p == config.name) {
+        *pinned = new_name.clone();
+    }
+    if let Err(e) = state.save(dirs) {
+        log::warn!("Could not save state: {}", e);
+    }
+    status!("Renamed {} to {}", config.name, new_name);
+
+    Ok(())
+}
+
+// Checks the setup and prints one ✓/✗ line per item. Only problems that stop switching
+// altogether (no home dir, no claude, no config dirs, ccr missing for CCR profiles) fail the command.
+pub fn doctor(dirs: &ConfigDirs, ccr_bin: &str) -> Result<()> {
+    let mut problems = 0;
+    let mut critical = 0;
+    let mut check = |ok: bool, is_critical: bool, label: &str, detail: String| {
+        println!("{} {}: {}", if ok { "✓" } else { "✗" }, label, detail);
+        if !ok {
+            problems += 1;
+            if is_critical {
+                critical += 1;
+            }
+        }
+    };
+
+    match dirs::home_dir() {
+        Some(home) => check(true, true, "Home directory", home.display().to_string()),
+        None => check(false, true, "Home directory", "could not be resolved".to_string()),
+    }
+
+    let claude_dir_exists = dirs.claude.is_dir();
+    let router_dir_exists = dirs.router.is_dir();
+    let no_dirs = !claude_dir_exists && !router_dir_exists;
+    check(claude_dir_exists, no_dirs, "Claude directory", dir_detail(&dirs.claude, claude_dir_exists));
+    check(router_dir_exists, no_dirs, "CodeRouter directory", dir_detail(&dirs.router, router_dir_exists));
+
+    match resolve_claude_command() {
+        Some((path, source)) => check(true, true, "claude", format!("{} ({})", path, source.describe())),
+        None => check(false, true, "claude", "not found, install it with `npm install -g @anthropic-ai/claude-code`".to_string()),
+    }
+
+    let configs = crate::config::load_configurations(dirs, SortOrder::default())?;
+    let has_ccr_profiles = configs.iter().any(|c| matches!(c.config_type, ConfigType::CodeRouter));
+    match resolve_ccr_command(ccr_bin) {
+        Some((path, source)) => check(true, has_ccr_profiles, "ccr", format!("{} ({})", path, source.describe())),
+        None => check(false, has_ccr_profiles, "ccr", format!("{} not found", ccr_bin)),
+    }
+
+    if configs.is_empty() {
+        check(false, false, "Configurations", "none found".to_string());
+    }
+    for config in &configs {
+        let detail = match &config.error {
+            Some(error) => format!("{} ({})", config.path.display(), error),
+            None => config.path.display().to_string(),
+        };
+        check(config.error.is_none(), false, &config.name, detail);
+    }
+
+    println!();
+    if critical > 0 {
+        anyhow::bail!("{} problem(s) found, {} critical", problems, critical);
+    }
+    if problems > 0 {
+        println!("{} problem(s) found, none critical", problems);
+    } else {
+        println!("Everything looks good");
+    }
+
+    Ok(())
+}
+
+// Parses every profile and checks the keys its type relies on; fails if any profile does
+pub fn validate_profiles(dirs: &ConfigDirs, type_filter: Option<ConfigType>) -> Result<()> {
+    let configs = crate::config::load_configurations(dirs, SortOrder::default())?
+        .into_iter()
+        .filter(|c| type_filter.is_none_or(|t| c.config_type == t))
+        .collect::<Vec<_>>();
+
+    if configs.is_empty() {
+        match type_filter {
+            Some(t) => println!("No {} configurations found", t.as_str()),
+            None => println!("{}", crate::config::no_configurations_hint(dirs)),
+        }
+        return Ok(());
+    }
+
+    let width = configs.iter().map(|c| c.name.width()).max().unwrap_or(0);
+    let mut failed = 0;
+    for config in &configs {
+        let problems = match crate::config::read_profile(&config.path) {
+            Ok(value) => crate::config::check_profile(&value, config.config_type),
+            Err(e) => vec![format!("{:#}", e)],
+        };
+        let padding = " ".repeat(width - config.name.width());
+        if problems.is_empty() {
+            println!("✓ {}{}  ok", config.name, padding);
+        } else {
+            failed += 1;
+            println!("✗ {}{}  {}", config.name, padding, problems.join("; "));
+        }
+    }
+
+    println!();
+    if failed > 0 {
+        anyhow::bail!("{} of {} configuration(s) failed validation", failed, configs.len());
+    }
+    println!("All {} configuration(s) passed", configs.len());
+
+    Ok(())
+}
+
+fn dir_detail(dir: &Path, exists: bool) -> String {
+    if exists {
+        dir.display().to_string()
+    } else {
+        format!("{} (missing)", dir.display())
+    }
+}
+
+// Opens the profile in $EDITOR and keeps offering to reopen it until it parses again
+pub fn edit_profile(dirs: &ConfigDirs, name: &str) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, name)?;
+    let editor = env::var("EDITOR")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(target_os = "windows") { "notepad" } else { "nano" }.to_string());
+
+    // EDITOR may carry arguments, e.g. "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or_default();
+    let args = parts.collect::<Vec<_>>();
+
+    loop {
+        let status = std::process::Command::new(program)
+            .args(&args)
+            .arg(crate::config::profile_file(&config.path))
+            .status()
+            .map_err(|e| anyhow::anyhow!("Could not start editor '{}': {}", editor, e))?;
+        if !status.success() {
+            anyhow::bail!("Editor '{}' exited with status: {}", editor, status);
+        }
+
+        match crate::config::validate_config_file(&config.path) {
+            None => {
+                println!("{} is valid", config.path.display());
+                return Ok(());
+            }
+            Some(error) => {
+                println!("{}", error);
+                if !confirm("Reopen the editor?")? {
+                    anyhow::bail!("{} was left invalid", config.path.display());
+                }
+            }
+        }
+    }
+}
+
+// An empty description removes the key; every other key is written back unchanged
+pub fn describe_profile(dirs: &ConfigDirs, name: &str, description: &str) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, name)?;
+    if let Some(error) = &config.error {
+        anyhow::bail!("Cannot read {}: {}", config.name, error);
+    }
+
+    // An entry of profiles.json is updated in place, leaving the other entries as they are
+    let file = crate::config::profile_file(&config.path);
+    let mut value = crate::config::read_profile_raw(&file)?;
+    let profile = match crate::config::profile_entry(&config.path) {
+        Some((_, entry)) => value.get_mut(entry),
+        None => Some(&mut value),
+    };
+    let Some(obj) = profile.and_then(|p| p.as_object_mut()) else {
+        anyhow::bail!("{} does not contain a JSON object", config.path.display());
+    };
+
+    if description.is_empty() {
+        obj.shift_remove("description");
+        status!("Removed description of {}", config.name);
+    } else {
+        obj.insert("description".to_string(), serde_json::Value::String(description.to_string()));
+        status!("Updated description of {}", config.name);
+    }
+
+    crate::config::write_profile(&file, &value)?;
+    Ok(())
+}
+
+// Only the path goes to stdout, so `$(claude-codust path work)` can be handed to other tools.
+// Broken profiles are printed too, since opening them is how they get fixed.
+pub fn print_profile_path(dirs: &ConfigDirs, name: &str) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, name)?;
+    println!("{}", std::path::absolute(crate::config::profile_file(&config.path))?.display());
+    Ok(())
+}
+
+// Prints the variables `code <name>` would set, quoted so the output can be eval'd
+pub fn print_profile_env(dirs: &ConfigDirs, name: &str, show_secrets: bool, export: bool) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, name)?;
+    if let Some(error) = &config.error {
+        anyhow::bail!("Cannot read {}: {}", config.name, error);
+    }
+
+    let value = crate::config::read_profile(&config.path)?;
+    let profile_env = crate::config::build_launch_env(&value, &config.config_type, HashMap::new(), EnvMode::Overlay)?;
+
+    let mut keys = profile_env.keys().collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        let value = if crate::config::is_secret_key(key) && !show_secrets {
+            crate::config::mask_value(&profile_env[key])
+        } else {
+            profile_env[key].clone()
+        };
+        let prefix = if export { "export " } else { "" };
+        println!("{}{}={}", prefix, key, shell_quote(&value));
+    }
+
+    Ok(())
+}
+
+// Key-by-key differences between two profiles, secrets masked unless show_secrets.
+// `-` lines are only in the first profile, `+` only in the second, `~` differ.
+pub fn diff_configurations(dirs: &ConfigDirs, first: &str, second: &str, show_secrets: bool, color: bool) -> Result<()> {
+    let mut values = Vec::new();
+    let mut types = Vec::new();
+    for name in [first, second] {
+        let config = crate::config::find_configuration(dirs, name)?;
+        if let Some(error) = &config.error {
+            anyhow::bail!("Cannot read {}: {}", config.name, error);
+        }
+        let value = crate::config::read_profile(&config.path)?;
+        values.push(value);
+        types.push(config.config_type);
+    }
+
+    if types[0] != types[1] {
+        println!(
+            "Note: {} is a {} configuration and {} a {} one, so most keys are expected to differ",
+            first, types[0].as_str(), second, types[1].as_str()
+        );
+        println!();
+    }
+
+    let changes = crate::config::diff_profiles(&values[0], &values[1]);
+    if changes.is_empty() {
+        println!("{} and {} are identical", first, second);
+        return Ok(());
+    }
+
+    // Compared unmasked so keys that only share their last characters still show up as changed
+    let shown = |key: &str, value: &serde_json::Value| {
+        let last = key.rsplit('.').next().unwrap_or(key);
+        match value.as_str() {
+            _ if show_secrets => value.to_string(),
+            Some(secret) if crate::config::is_secret_key(last) => format!("\"{}\"", crate::config::mask_value(secret)),
+            _ => crate::config::mask_secrets(value).to_string(),
+        }
+    };
+    let color = color && io::stdout().is_terminal();
+    for change in &changes {
+        let (line, style): (String, fn(String) -> crossterm::style::StyledContent<String>) = match change {
+            crate::config::ProfileChange::Removed(key, value) => (format!("- {}: {}", key, shown(key, value)), |s| s.red()),
+            crate::config::ProfileChange::Added(key, value) => (format!("+ {}: {}", key, shown(key, value)), |s| s.green()),
+            crate::config::ProfileChange::Changed(key, old, new) => {
+                (format!("~ {}: {} → {}", key, shown(key, old), shown(key, new)), |s| s.yellow())
+            }
+        };
+        if color {
+            println!("{}", style(line));
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+// The selector's preview on stdout: the profile as applied (bases merged in) as JSON, or with
+// --raw the file exactly as written. Secrets are masked either way unless --show-secrets.
+pub fn show_profile(dirs: &ConfigDirs, name: &str, raw: bool, show_secrets: bool, color: bool) -> Result<()> {
+    let config = crate::config::find_configuration(dirs, name)?;
+
+    if raw {
+        // An entry of profiles.json has no text of its own, so it is shown as JSON
+        let content = match crate::config::profile_entry(&config.path) {
+            Some(_) => format!("{}\n", serde_json::to_string_pretty(&crate::config::read_profile_raw(&config.path)?)?),
+            None => fs::read_to_string(&config.path)?,
+        };
+        let content = match crate::config::read_profile_raw(&config.path) {
+            Ok(_) if show_secrets => content,
+            Ok(value) => crate::config::mask_secrets_in_text(&content, &value),
+            // Without parsing there is no telling which parts are secrets
+            Err(e) if !show_secrets => anyhow::bail!("{:#}; use --show-secrets to print it anyway", e),
+            Err(_) => content,
+        };
+        print!("{}", content);
+        return Ok(());
+    }
+
+    if let Some(error) = &config.error {
+        anyhow::bail!("Cannot read {}: {}", config.name, error);
+    }
+    let value = crate::config::read_profile(&config.path)?;
+    let value = if show_secrets { value } else { crate::config::mask_secrets(&value) };
+    let color = color && io::stdout().is_terminal();
+    for line in serde_json::to_string_pretty(&value)?.lines() {
+        if color {
+            println!("{}", color_json_line(line));
+        } else {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+// Keys cyan, strings green, numbers and booleans yellow; works line by line on pretty JSON
+fn color_json_line(line: &str) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let rest = line.trim_start();
+    let (key, value) = match rest.split_once("\": ") {
+        Some((key, value)) if rest.starts_with('"') => (Some(format!("{}\"", key)), value),
+        _ => (None, rest),
+    };
+    let (value, comma) = value.strip_suffix(',').map_or((value, ""), |v| (v, ","));
+    let value = match value.chars().next() {
+        Some('"') => value.green().to_string(),
+        Some('-' | '0'..='9' | 't' | 'f') => value.yellow().to_string(),
+        Some('n') => value.dark_grey().to_string(),
+        _ => value.to_string(),
+    };
+    match key {
+        Some(key) => format!("{}{}: {}{}", indent, key.cyan(), value, comma),
+        None => format!("{}{}{}", indent, value, comma),
+    }
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    let answer = prompt(&format!("{} [y/N]", question))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn prompt(label: &str) -> Result<String> {
+    print!("{}: ", label);
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_string())
+}
+
+// Only removes the file if it still holds exactly what the profile wrote, so a file the user
+// maintains by hand (or edited during the session) is left alone
+fn cleanup_local_settings(config_path: &Path) -> Result<()> {
+    let local_settings_path = crate::config::local_settings_path()?;
+    let expected = crate::config::read_json_file(config_path)
+        .and_then(|config| crate::config::local_settings(&config));
+
+    if expected.is_some() && crate::config::read_json_file(&local_settings_path) == expected {
         fs::remove_file(&local_settings_path)?;
-        println!("\r\nCleaned up local settings file: {}", local_settings_path.display());
+        log::info!("Cleaned up local settings file: {}", local_settings_path.display());
+    } else if local_settings_path.exists() {
+        log::info!("Leaving {} in place, it was not written by this profile", local_settings_path.display());
     }
     
     Ok(())
 }
 
-pub async fn switch_configuration(config: &ConfigItem) -> Result<()> {
-    let home = home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
-    
+// Switches once, or with --watch keeps relaunching claude with the configuration reapplied
+// each time its file changes, until claude exits on its own
+pub async fn run_configuration(config: &ConfigItem, options: &SwitchOptions) -> Result<i32> {
+    if !options.watch || !options.launch || options.dry_run || config.error.is_some() {
+        return switch_configuration(config, options).await;
+    }
+
+    let watcher = crate::watch::watch_file(&crate::config::profile_file(&config.path))?;
+    let mut changes = watcher.changes.clone();
+    status!("Watching {} for changes, exit claude to stop", config.path.display());
+
+    loop {
+        // Clones start out having seen what `changes` has, so only newer changes stop claude
+        changes.borrow_and_update();
+        let options = SwitchOptions {
+            reload: Some(changes.clone()),
+            ..options.clone()
+        };
+        match switch_configuration(config, &options).await {
+            Ok(code) if !changes.has_changed().unwrap_or(false) => return Ok(code),
+            Ok(_) => {}
+            Err(e) => {
+                // Most likely a half-finished edit; wait for the next save instead of giving up
+                log::error!("{:#}", e);
+                status!("Waiting for {} to change, press Ctrl+C to stop", config.path.display());
+                tokio::select! {
+                    changed = changes.changed() => changed?,
+                    _ = tokio::signal::ctrl_c() => return Err(e),
+                }
+            }
+        }
+
+        // Editors may save in several writes; let them finish before the file is read again
+        tokio::time::sleep(WATCH_SETTLE_TIME).await;
+        status!("\r\n{} changed, relaunching claude", config.name);
+    }
+}
+
+const WATCH_SETTLE_TIME: Duration = Duration::from_millis(300);
+
+// Returns claude's exit code, or 0 for a dry run
+pub async fn switch_configuration(config: &ConfigItem, options: &SwitchOptions) -> Result<i32> {
+    if let Some(error) = &config.error {
+        anyhow::bail!("Configuration '{}' is invalid ({}): {}", config.name, config.path.display(), error);
+    }
+
+    // Check before touching any files so a missing CLI doesn't leave a half-applied switch
+    let claude_path = if let (true, Some(command)) = (options.launch, &options.launch_cmd) {
+        Some(command.clone())
+    } else if options.launch {
+        Some(find_claude_command().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Claude CLI not found. Install it with `npm install -g @anthropic-ai/claude-code` and make sure `claude` is on your PATH"
+            )
+        })?)
+    } else {
+        None
+    };
+
     match config.config_type {
         ConfigType::Claude => {
-            crate::config::backup_settings_json_if_exists(&home, &config.path)?;
-            
-            println!("\r\nSwitched to Claude configuration: {}", config.name);
-            
-            launch_claude_with_config(&config.path, &config.config_type).await?;
+            let claude_dir = &options.dirs.claude;
+            crate::config::ensure_replaceable(&claude_dir.join("settings.json"), false)?;
+            if !options.dry_run && claude_dir.join("settings.json").exists() {
+                crate::backup::ensure_backup_dir(&options.backup_dir)?;
+            }
+
+            if options.dry_run {
+                // Leave the selector's screen first, or these lines vanish with it
+                crate::ui::restore_terminal()?;
+                println!("\r\n[dry-run] Would remove ANTHROPIC_* keys from {}", claude_dir.join("settings.json").display());
+                println!("\r\n[dry-run] Would write non-env keys of {} to .claude/settings.local.json", config.path.display());
+            } else {
+                crate::config::backup_settings_json_if_exists(claude_dir, &config.path, &options.backup_dir, options.backup_count)?;
+                status!("\r\nSwitched to Claude configuration: {}", config.name);
+                crate::state::record_last_used(&options.dirs, &config.name);
+            }
+
+            let Some(claude_path) = claude_path else {
+                // The env block only reaches claude when we launch it
+                status!("Run `claude-codust code {}` to launch claude with its environment", config.name);
+                return Ok(0);
+            };
+            launch_claude_with_config(&config.path, &config.config_type, &claude_path, false, options).await
         }
         ConfigType::CodeRouter => {
-            let target_path = home.join(".claude-code-router").join("config.json");
+            let target_path = options.dirs.router.join("config.json");
+            // A --link symlink is removed rather than written through, so only a real file has to be writable
+            let is_symlink = target_path.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink());
+            crate::config::ensure_replaceable(&target_path, !is_symlink)?;
+
+            // Catch a malformed HOST/SCHEME before touching config.json or restarting CCR
+            if let Some(profile) = crate::config::read_json_file(&config.path) {
+                crate::config::router_base_url(&profile)?;
+            }
+
+            if options.dry_run {
+                crate::ui::restore_terminal()?;
+                let action = if options.link { "link" } else { "copy" };
+                println!("\r\n[dry-run] Would {} {} to {}", action, config.path.display(), target_path.display());
+                if options.ccr_restart {
+                    println!("\r\n[dry-run] Would run {} restart", options.ccr_bin);
+                }
+
+                return match claude_path {
+                    Some(claude_path) => {
+                        launch_claude_with_config(&config.path, &config.config_type, &claude_path, options.ccr_restart, options).await
+                    }
+                    None => Ok(0),
+                };
+            }
             
+            let port = crate::config::read_json_file(&config.path)
+                .map(|profile| crate::config::router_port(&profile))
+                .unwrap_or_else(|| crate::config::DEFAULT_ROUTER_PORT.to_string());
+            if port_taken_by_other_process(&port, &options.ccr_bin, options.ccr_timeout).await {
+                if !options.force {
+                    anyhow::bail!(
+                        "Port {} is already in use by a process other than CCR, claude would connect to the wrong server. Use --force to switch anyway",
+                        port
+                    );
+                }
+                log::warn!("Port {} is already in use by a process other than CCR", port);
+            }
+
+            if !keep_or_save_router_edits(&target_path, options)? {
+                status!("Cancelled");
+                return Ok(0);
+            }
+
             if let Some(parent) = target_path.parent() {
                 fs::create_dir_all(parent)?;
             }
             
-            fs::copy(&config.path, &target_path)?;
-            println!("\r\nSwitched to Claude Code Router configuration: {}", config.name);
-            println!("\r\nCopied {} to {}", config.path.display(), target_path.display());
-            
-            run_ccr_restart().await?;
-            
-            launch_claude_with_config(&target_path, &config.config_type).await?;
+            let previous = crate::config::read_json_file(&target_path);
+
+            status!("\r\nSwitched to Claude Code Router configuration: {}", config.name);
+            install_router_config(&config.path, &target_path, options.link)?;
+            crate::state::record_last_used(&options.dirs, &config.name);
+
+            let current = crate::config::read_json_file(&target_path);
+            crate::state::record_router_config(&options.dirs, &config.name, current.as_ref());
+            let port = current.as_ref()
+                .map(crate::config::router_port)
+                .unwrap_or_else(|| crate::config::DEFAULT_ROUTER_PORT.to_string());
+            // Probed where claude will connect, which may be a HOST other than this machine
+            let host = current.as_ref()
+                .and_then(|config| crate::config::router_host(config).ok())
+                .unwrap_or("127.0.0.1");
+
+            // A changed config (including a different PORT) always needs a restart
+            let mut started_ccr = false;
+            if !options.ccr_restart {
+                log::info!("Not restarting CCR (--no-ccr-restart), it may still be running with the previous configuration");
+            } else if previous.is_some() && previous == current && ccr_is_running(host, &port).await {
+                status!("\r\nCCR is already running with this configuration, skipping restart");
+            } else {
+                run_ccr_restart(&options.ccr_bin, options.ccr_timeout).await?;
+                started_ccr = true;
+            }
+
+            let Some(claude_path) = claude_path else {
+                // CCR keeps running for a claude started later
+                return Ok(0);
+            };
+            launch_claude_with_config(&target_path, &config.config_type, &claude_path, started_ccr, options).await
+        }
+    }
+}
+
+// If config.json no longer matches what the last switch wrote, it was edited by hand. Warns,
+// offers to save it as a profile and asks whether to overwrite it; returns false to cancel.
+// A symlinked config.json is never checked since edits went straight into the linked profile.
+fn keep_or_save_router_edits(target_path: &Path, options: &SwitchOptions) -> Result<bool> {
+    let is_symlink = target_path.symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false);
+    let Some(recorded) = crate::state::State::load(&options.dirs).router_digest else {
+        return Ok(true);
+    };
+    let Some(current) = crate::config::read_json_file(target_path) else {
+        return Ok(true);
+    };
+    if is_symlink || crate::state::config_digest(&current) == recorded {
+        return Ok(true);
+    }
+
+    if options.force {
+        log::warn!("{} has local edits that will be overwritten", target_path.display());
+        return Ok(true);
+    }
+
+    // Scripts can't answer the prompts, and reading EOF as "no" would pass for success
+    if !io::stdin().is_terminal() {
+        anyhow::bail!(
+            "{} was edited since the last switch. Use --force to overwrite these edits",
+            target_path.display()
+        );
+    }
+
+    // The selector may still own the terminal; prompts need normal line input
+    crate::ui::restore_terminal()?;
+
+    println!("{} was edited since the last switch, switching will overwrite these edits.", target_path.display());
+    let name = prompt("Save the current config.json as a new profile first? Name (leave empty to skip)")?;
+    if !name.is_empty() {
+        if name.contains(['/', '\\']) {
+            anyhow::bail!("Invalid profile name: '{}'", name);
+        }
+        let path = options.dirs.router.join(format!("{}-config.json", name));
+        if path.exists() {
+            anyhow::bail!("{} already exists", path.display());
+        }
+        fs::copy(target_path, &path)?;
+        status!("Saved {}", path.display());
+        return Ok(true);
+    }
+
+    confirm("Overwrite it anyway?")
+}
+
+fn install_router_config(source: &Path, target: &Path, link: bool) -> Result<()> {
+    // Never copy through a symlink left by a previous --link switch, it would overwrite the linked profile
+    if target.symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+        fs::remove_file(target)?;
+    }
+
+    // CCR only reads JSON with everything in one file, so YAML and TOML profiles and ones
+    // that extend a base are converted rather than copied or linked
+    let is_json = source.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let extends = crate::config::read_profile_raw(source).is_ok_and(|raw| raw.get("extends").is_some());
+    if !is_json || extends {
+        if link {
+            log::warn!("Cannot link {}, CCR needs a single JSON file; writing the merged configuration instead", source.display());
+        }
+        crate::config::write_profile(target, &crate::config::read_profile(source)?)?;
+        log::info!("Converted {} to {}", source.display(), target.display());
+        return Ok(());
+    }
+
+    if link {
+        if target.symlink_metadata().is_ok() {
+            fs::remove_file(target)?;
+        }
+
+        let source = fs::canonicalize(source)?;
+        match create_symlink(&source, target) {
+            Ok(()) => {
+                log::info!("Linked {} to {}", target.display(), source.display());
+                return Ok(());
+            }
+            Err(e) => {
+                log::warn!("Could not create symlink ({}), copying instead", e);
+            }
+        }
+    }
+
+    crate::config::write_atomic(target, &fs::read(source)?)?;
+    log::info!("Copied {} to {}", source.display(), target.display());
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(source: &Path, target: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, target)
+}
+
+#[cfg(windows)]
+fn create_symlink(source: &Path, target: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, target)
+}
+
+// Hook commands are shell snippets from the profile and see the same environment as claude;
+// the first one that fails stops the rest
+async fn run_hooks(kind: &str, commands: &[String], env_vars: &HashMap<String, String>) -> Result<()> {
+    for command in commands {
+        log::debug!("Running {} hook: {}", kind, command);
+        let mut shell = if cfg!(target_os = "windows") {
+            let mut shell = TokioCommand::new("cmd");
+            shell.args(["/C", command]);
+            shell
+        } else {
+            let mut shell = TokioCommand::new("sh");
+            shell.args(["-c", command]);
+            shell
+        };
+        let mut child = shell
+            .env_clear()
+            .envs(env_vars)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let status = wait_for_child(&mut child).await?;
+        if !status.success() {
+            anyhow::bail!("{} hook `{}` failed with {}", kind, command, status);
         }
     }
-    
     Ok(())
 }
 
-async fn launch_claude_with_config(config_path: &PathBuf, config_type: &ConfigType) -> Result<()> {
-    let config_content = fs::read_to_string(config_path)?;
-    let config: serde_json::Value = serde_json::from_str(&config_content)?;
+// Runs claude directly with a proper argv so paths and arguments need no quoting. Only if that
+// fails (e.g. a `claude.cmd` shim on Windows) does it go through the shell.
+// env_vars is the complete environment, so variables stripped from it must not leak back in.
+fn spawn_claude(claude_path: &str, args: &[String], env_vars: &HashMap<String, String>) -> Result<Child> {
+    match spawn_with_env(TokioCommand::new(claude_path).args(args), env_vars) {
+        Ok(child) => return Ok(child),
+        Err(e) => log::debug!("Could not run {} directly ({}), trying through the shell", claude_path, e),
+    }
+
+    if cfg!(target_os = "windows") {
+        spawn_through_shell(claude_path, args, env_vars)
+    } else {
+        spawn_through_shell(&shell_quote(claude_path), args, env_vars)
+    }
+}
+
+// `command` is handed to the shell as written, so it can carry its own arguments (--launch-cmd);
+// `args` are quoted and appended
+fn spawn_through_shell(command: &str, args: &[String], env_vars: &HashMap<String, String>) -> Result<Child> {
+    let child = if cfg!(target_os = "windows") {
+        // Rust quotes each argv entry for cmd, so arguments with spaces survive as-is
+        spawn_with_env(TokioCommand::new("cmd").args(["/C", command]).args(args), env_vars)?
+    } else {
+        let command_line = std::iter::once(command.to_string())
+            .chain(args.iter().map(|arg| shell_quote(arg)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        spawn_with_env(TokioCommand::new("sh").args(["-c", &command_line]), env_vars)?
+    };
+    Ok(child)
+}
+
+fn spawn_with_env(command: &mut TokioCommand, env_vars: &HashMap<String, String>) -> io::Result<Child> {
+    command
+        .env_clear()
+        .envs(env_vars)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn()
+}
+
+fn should_stop_ccr(ccr_stop: CcrStop, started_ccr: bool) -> bool {
+    match ccr_stop {
+        CcrStop::IfStarted => started_ccr,
+        CcrStop::Always => true,
+        CcrStop::Never => false,
+    }
+}
+
+async fn launch_claude_with_config(
+    config_path: &Path,
+    config_type: &ConfigType,
+    claude_path: &str,
+    started_ccr: bool,
+    options: &SwitchOptions,
+) -> Result<i32> {
+    let config = crate::config::read_profile(config_path)?;
     
-    let mut env_vars = env::vars().collect::<HashMap<String, String>>();
-    match config_type {
-        ConfigType::Claude => {
-            if let Some(env_obj) = config.get("env").and_then(|e| e.as_object()) {
-                for (key, value) in env_obj {
-                    if let Some(value_str) = value.as_str() {
-                        env_vars.insert(key.clone(), value_str.to_string());
-                    }
-                }
+    let mut inherited = env::vars().collect::<HashMap<String, String>>();
+    if matches!(config_type, ConfigType::Claude) && !options.keep_anthropic_env {
+        crate::config::strip_inherited_anthropic_vars(&mut inherited, &config);
+    }
+    let env_vars = crate::config::build_launch_env(&config, config_type, inherited, options.env_mode)?;
+    
+    if options.dry_run {
+        crate::ui::restore_terminal()?;
+
+        // Only the profile's own variables are worth showing, not the whole inherited environment;
+        // in underlay mode the ones already inherited don't take effect
+        let profile_env = crate::config::build_launch_env(&config, config_type, HashMap::new(), EnvMode::Overlay)?;
+        let mut keys = profile_env.keys()
+            .filter(|key| env_vars.get(*key) == profile_env.get(*key))
+            .collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let value = if crate::config::is_secret_key(key) && !options.show_secrets {
+                crate::config::mask_value(&profile_env[key])
+            } else {
+                profile_env[key].clone()
+            };
+            println!("[dry-run] Would set {}={}", key, value);
+        }
+        let command_line = std::iter::once(claude_path)
+            .chain(options.claude_args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if options.hooks {
+            for command in crate::config::launch_hooks(&config, "preLaunch") {
+                println!("[dry-run] Would run preLaunch hook: {}", command);
             }
         }
-        ConfigType::CodeRouter => {
-            if let Some(api_key) = config.get("APIKEY").and_then(|k| k.as_str()) {
-                env_vars.insert("ANTHROPIC_API_KEY".to_string(), api_key.to_string());
-            } else {
-                env_vars.insert("ANTHROPIC_AUTH_TOKEN".to_string(), "test".to_string());
+        println!("[dry-run] Would launch {}", command_line);
+        if options.hooks {
+            for command in crate::config::launch_hooks(&config, "postLaunch") {
+                println!("[dry-run] Would run postLaunch hook: {}", command);
             }
-            
-            let port = config.get("PORT")
-                .and_then(|p| p.as_str())
-                .unwrap_or("3456");
-            let base_url = format!("http://127.0.0.1:{}", port);
-            env_vars.insert("ANTHROPIC_BASE_URL".to_string(), base_url);
         }
+        if matches!(config_type, ConfigType::CodeRouter) && should_stop_ccr(options.ccr_stop, started_ccr) {
+            println!("[dry-run] Would run {} stop after claude exits", options.ccr_bin);
+        }
+        return Ok(0);
     }
     
-    let claude_path = find_claude_command()?;
-    
-    execute!(io::stdout(), Show, LeaveAlternateScreen)?;
-    disable_raw_mode()?;
+    crate::ui::restore_terminal()?;
     
-    println!("Launching Claude with configuration environment...");
+    status!("Launching Claude with configuration environment...");
+
+    // Only the variables the profile changes; values are left out since they are often secrets
+    let mut changed = env_vars.iter()
+        .filter(|(key, value)| env::var(key).ok().as_ref() != Some(*value))
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+    changed.sort();
+    for key in changed {
+        log::debug!("Setting {} for claude", key);
+    }
+    log::debug!("Spawning {} {:?}", claude_path, options.claude_args);
     
-    let mut child = if cfg!(target_os = "windows") {
-        TokioCommand::new("cmd")
-            .args(["/C", &claude_path])
-            .envs(&env_vars)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?
+    let (pre_hooks, post_hooks) = if options.hooks {
+        (crate::config::launch_hooks(&config, "preLaunch"), crate::config::launch_hooks(&config, "postLaunch"))
     } else {
-        TokioCommand::new("sh")
-            .args(["-c", &claude_path])
-            .envs(&env_vars)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?
+        (Vec::new(), Vec::new())
     };
-    
+
+    // The post hook runs whatever happened to claude, like stopping CCR below
+    let result = async {
+        run_hooks("preLaunch", &pre_hooks, &env_vars).await?;
+        let mut child = match &options.launch_cmd {
+            Some(command) => spawn_through_shell(command, &options.claude_args, &env_vars)?,
+            None => spawn_claude(claude_path, &options.claude_args, &env_vars)?,
+        };
+        wait_for_claude(&mut child, options.reload.clone()).await
+    }.await;
+    if let Err(e) = run_hooks("postLaunch", &post_hooks, &env_vars).await {
+        log::warn!("{}", e);
+    }
+
     if matches!(config_type, ConfigType::CodeRouter) {
-        let status = child.wait().await?;
-        
-        let _ = stop_ccr().await;
-        
-        if !status.success() {
-            eprintln!("Claude command exited with status: {}", status);
-        }
-    } else {
-        let status = child.wait().await?;
-        if !status.success() {
-            eprintln!("Claude command exited with status: {}", status);
+        if !should_stop_ccr(options.ccr_stop, started_ccr) {
+            match options.ccr_stop {
+                CcrStop::Never => log::info!("Leaving CCR running (--never-stop-ccr)"),
+                _ => log::info!("Leaving CCR running since this switch did not start it (--always-stop-ccr stops it anyway)"),
+            }
+        } else if let Err(e) = stop_ccr(&options.ccr_bin, options.ccr_timeout).await {
+            log::warn!("{}", e);
         }
-        
+    } else if !options.keep_local {
         // Clean up local settings for Claude configurations
-        let _ = cleanup_local_settings();
+        let _ = cleanup_local_settings(config_path);
+    }
+
+    let status = result?;
+    // Stopped by --watch to be relaunched, so neither the status nor the pause mean anything
+    let reloading = options.reload.as_ref().is_some_and(|r| r.has_changed().unwrap_or(false));
+    if !status.success() && !reloading {
+        eprintln!("Claude command exited with status: {}", status);
     }
     
-    println!("\nClaude session completed. Press any key to exit...");
+    // Scripts and pipes get control back immediately
+    if options.wait && !reloading && io::stdin().is_terminal() && io::stdout().is_terminal() {
+        println!("\nClaude session completed. Press any key to exit...");
+
+        enable_raw_mode()?;
+        loop {
+            if let Event::Key(_) = event::read()? {
+                break;
+            }
+        }
+        disable_raw_mode()?;
+    }
     
-    enable_raw_mode()?;
+    // Killed by a signal means there is no code; report a generic failure instead
+    Ok(status.code().unwrap_or(1))
+}
+
+// Ctrl+C reaches claude too, so swallow it here and keep waiting for claude to exit;
+// otherwise we would die first and skip stopping CCR and restoring the terminal
+async fn wait_for_child(child: &mut Child) -> Result<ExitStatus> {
     loop {
-        if let Event::Key(_) = event::read()? {
-            break;
+        tokio::select! {
+            status = child.wait() => return Ok(status?),
+            _ = tokio::signal::ctrl_c() => {}
         }
     }
-    disable_raw_mode()?;
-    
-    Ok(())
 }
 
-fn find_claude_command() -> Result<String> {
+// Under --watch a change to the profile stops claude early so it can be relaunched
+async fn wait_for_claude(child: &mut Child, reload: Option<watch::Receiver<u64>>) -> Result<ExitStatus> {
+    let Some(mut reload) = reload else {
+        return wait_for_child(child).await;
+    };
+    tokio::select! {
+        status = wait_for_child(child) => return status,
+        _ = reload.changed() => {}
+    }
+
+    log::info!("Configuration changed, stopping claude");
+    // SIGTERM first so claude can restore the terminal; killed if it doesn't exit in time
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        let _ = TokioCommand::new("kill").args(["-TERM", &pid.to_string()]).status().await;
+        if let Ok(status) = tokio::time::timeout(CLAUDE_STOP_TIMEOUT, child.wait()).await {
+            return Ok(status?);
+        }
+    }
+    child.kill().await?;
+    Ok(child.wait().await?)
+}
+
+const CLAUDE_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Single-quote an argument for `sh -c`, escaping embedded single quotes
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn find_on_path(program: &str) -> Option<String> {
     let which_cmd = if cfg!(target_os = "windows") { "where" } else { "which" };
     
-    if let Ok(output) = std::process::Command::new(which_cmd).arg("claude").output() {
-        if output.status.success() {
-            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !path.is_empty() {
-                return Ok(path);
+    let output = std::process::Command::new(which_cmd).arg(program).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // `where` may print several matches, the first one is what the shell would run
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().map(str::trim).find(|l| !l.is_empty()).map(|l| l.to_string())
+}
+
+// Where a command was found, reported by `which` and `doctor`
+#[derive(Debug, Clone, Copy)]
+enum CommandSource {
+    Path,
+    Fallback,
+    Override,
+}
+
+impl CommandSource {
+    fn describe(self) -> &'static str {
+        match self {
+            CommandSource::Path => if cfg!(target_os = "windows") { "found by where" } else { "found by which" },
+            CommandSource::Fallback => "common install location, not on PATH",
+            CommandSource::Override => "configured override",
+        }
+    }
+}
+
+fn find_claude_command() -> Option<String> {
+    let (path, source) = resolve_claude_command()?;
+    log::debug!("Using claude at {} ({})", path, source.describe());
+    Some(path)
+}
+
+fn resolve_claude_command() -> Option<(String, CommandSource)> {
+    if let Some(path) = find_on_path("claude") {
+        return Some((path, CommandSource::Path));
+    }
+
+    // Fall back to common install locations that may not be on PATH
+    common_claude_locations()
+        .into_iter()
+        .find(|p| p.is_file())
+        .map(|p| (p.display().to_string(), CommandSource::Fallback))
+}
+
+fn find_ccr_command(ccr_bin: &str) -> Option<String> {
+    resolve_ccr_command(ccr_bin).map(|(path, _)| path)
+}
+
+// A bare name is looked up on PATH like claude; a path (`~` allowed) is used as given
+fn resolve_ccr_command(ccr_bin: &str) -> Option<(String, CommandSource)> {
+    let source = if ccr_bin == DEFAULT_CCR_BIN { CommandSource::Path } else { CommandSource::Override };
+    if ccr_bin.contains(['/', '\\']) || ccr_bin.starts_with('~') {
+        let path = crate::config::expand_path(ccr_bin).ok()?;
+        return path.is_file().then(|| (path.display().to_string(), source));
+    }
+    find_on_path(ccr_bin).map(|path| (path, source))
+}
+
+pub const DEFAULT_CCR_BIN: &str = "ccr";
+
+// Prints where claude and ccr resolve from; fails if either can't be found
+pub fn print_command_paths(ccr_bin: &str) -> Result<()> {
+    let mut missing = Vec::new();
+    for (name, resolved) in [("claude", resolve_claude_command()), ("ccr", resolve_ccr_command(ccr_bin))] {
+        match resolved {
+            Some((path, source)) => println!("{:<7}{}  ({})", name, path, source.describe()),
+            None => {
+                println!("{:<7}not found", name);
+                missing.push(name);
             }
         }
     }
-    
-    Ok("claude".to_string())
+
+    if !missing.is_empty() {
+        anyhow::bail!("{} not found", missing.join(" and "));
+    }
+    Ok(())
 }
 
-async fn run_ccr_restart() -> Result<()> {
-    println!("\r\nRunning ccr restart...");
-    
-    let mut child = if cfg!(target_os = "windows") {
-        TokioCommand::new("cmd")
-            .args(["/C", "ccr", "restart"])
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?
+// Falls back to the bare name so the shell gets a chance to find it anyway
+fn ccr_command(ccr_bin: &str, subcommand: &str) -> TokioCommand {
+    let program = find_ccr_command(ccr_bin).unwrap_or_else(|| ccr_bin.to_string());
+    if cfg!(target_os = "windows") {
+        let mut command = TokioCommand::new("cmd");
+        command.args(["/C", &program, subcommand]);
+        command
     } else {
-        TokioCommand::new("sh")
-            .args(["-c", "ccr restart"])
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?
+        let mut command = TokioCommand::new("sh");
+        command.args(["-c", &format!("{} {}", shell_quote(&program), subcommand)]);
+        command
+    }
+}
+
+fn common_claude_locations() -> Vec<PathBuf> {
+    let mut locations = Vec::new();
+
+    if let Some(home) = dirs::home_dir() {
+        if cfg!(target_os = "windows") {
+            if let Some(data_dir) = dirs::data_dir() {
+                locations.push(data_dir.join("npm").join("claude.cmd"));
+            }
+        } else {
+            locations.push(home.join(".claude").join("local").join("claude"));
+            locations.push(home.join(".local").join("bin").join("claude"));
+            locations.push(home.join(".npm-global").join("bin").join("claude"));
+            locations.push(PathBuf::from("/usr/local/bin/claude"));
+            locations.push(PathBuf::from("/opt/homebrew/bin/claude"));
+        }
+    }
+
+    locations
+}
+
+pub const DEFAULT_CCR_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Kills the child if it hasn't exited in time so a stuck router can't hang the tool
+async fn wait_with_timeout(child: &mut Child, timeout: Duration, description: &str) -> Result<ExitStatus> {
+    match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => Ok(status?),
+        Err(_) => {
+            let _ = child.kill().await;
+            anyhow::bail!("{} did not finish within {}s and was killed", description, timeout.as_secs())
+        }
+    }
+}
+
+const CCR_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+async fn ccr_is_running(host: &str, port: &str) -> bool {
+    let Ok(port) = port.parse::<u16>() else {
+        return false;
+    };
+
+    matches!(
+        tokio::time::timeout(CCR_PROBE_TIMEOUT, TcpStream::connect((host, port))).await,
+        Ok(Ok(_))
+    )
+}
+
+// The port counts as taken when it can't be bound locally while `ccr status` doesn't report a
+// running router. If ccr itself can't be asked, the port is given the benefit of the doubt.
+async fn port_taken_by_other_process(port: &str, ccr_bin: &str, timeout: Duration) -> bool {
+    let Ok(port) = port.parse::<u16>() else {
+        return false;
     };
+    if std::net::TcpListener::bind(("127.0.0.1", port)).is_ok() {
+        return false;
+    }
+
+    log::debug!("Port {} is bound, asking ccr status", port);
+    let command = ccr_command(ccr_bin, "status").stdin(Stdio::null()).output();
+    match tokio::time::timeout(timeout, command).await {
+        // A missing ccr fails through the shell too (exit 127) rather than failing to spawn
+        Ok(Ok(output)) if output.status.success() => !reports_running(&String::from_utf8_lossy(&output.stdout)),
+        _ => false,
+    }
+}
+
+// `ccr status` prints lines like "Status: Running" or "Status: Not Running". Only a "running"
+// that stands as a word of its own and isn't negated counts.
+fn reports_running(status: &str) -> bool {
+    status.lines().any(|line| {
+        let words = line
+            .split(|c: char| !c.is_alphanumeric() && c != '\'')
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+        words.iter().enumerate().any(|(i, word)| {
+            word == "running" && !(i > 0 && matches!(words[i - 1].as_str(), "not" | "no" | "isn't"))
+        })
+    })
+}
+
+async fn run_ccr_restart(ccr_bin: &str, timeout: Duration) -> Result<()> {
+    let message = format!("Running {} restart...", ccr_bin);
+    let quiet = crate::QUIET.load(std::sync::atomic::Ordering::Relaxed);
+    let animate = io::stdout().is_terminal() && !quiet;
+    if !animate {
+        status!("\r\n{}", message);
+    }
+
+    // With the spinner on screen ccr's output is held back and printed once it is done,
+    // otherwise the two would overwrite each other. --quiet holds it back too.
+    let output = if animate || quiet { Stdio::piped } else { Stdio::inherit };
+    let mut child = ccr_command(ccr_bin, "restart")
+        .stdin(Stdio::inherit())
+        .stdout(output())
+        .stderr(output())
+        .spawn()?;
     
-    let status = child.wait().await?;
+    log::debug!("Spawned ccr restart");
+    let status = if animate || quiet {
+        let (mut stdout, mut stderr) = (child.stdout.take(), child.stderr.take());
+        let restart = async {
+            tokio::join!(wait_with_timeout(&mut child, timeout, "ccr restart"), read_all(&mut stdout), read_all(&mut stderr))
+        };
+        let (status, stdout, stderr) = if animate { with_spinner(&message, restart).await } else { restart.await };
+        status!("{}", message);
+        // Quiet runs only show what ccr printed when the restart failed
+        if !quiet || !status.as_ref().is_ok_and(ExitStatus::success) {
+            io::stdout().write_all(&stdout)?;
+            io::stderr().write_all(&stderr)?;
+        }
+        status?
+    } else {
+        wait_with_timeout(&mut child, timeout, "ccr restart").await?
+    };
     
     if !status.success() {
-        println!("\r\nWarning: ccr restart command exited with status: {}", status);
+        log::warn!("ccr restart command exited with status: {}", status);
     } else {
-        println!("\r\nccr restart completed successfully");
+        log::info!("ccr restart completed successfully");
     }
     
     Ok(())
 }
 
-async fn stop_ccr() -> Result<()> {
-    println!("\r\nStopping CCR...");
-    
-    let mut child = if cfg!(target_os = "windows") {
-        TokioCommand::new("cmd")
-            .args(["/C", "ccr", "stop"])
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()?
-    } else {
-        TokioCommand::new("sh")
-            .args(["-c", "ccr stop"])
-            .stdin(Stdio::null())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()?
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+// Animates `message` on a new line until `task` finishes, then clears that line for
+// whatever is printed next
+async fn with_spinner<T>(message: &str, task: impl std::future::Future<Output = T>) -> T {
+    tokio::pin!(task);
+    let mut ticks = tokio::time::interval(Duration::from_millis(80));
+    let mut frame = 0;
+    print!("\r\n");
+    let result = loop {
+        tokio::select! {
+            result = &mut task => break result,
+            _ = ticks.tick() => {
+                print!("\r{} {}", SPINNER_FRAMES[frame % SPINNER_FRAMES.len()], message);
+                let _ = io::stdout().flush();
+                frame += 1;
+            }
+        }
     };
+    let _ = execute!(
+        io::stdout(),
+        crossterm::cursor::MoveToColumn(0),
+        crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine)
+    );
+    result
+}
+
+async fn read_all(pipe: &mut Option<impl tokio::io::AsyncRead + Unpin>) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    if let Some(pipe) = pipe {
+        let _ = tokio::io::AsyncReadExt::read_to_end(pipe, &mut buffer).await;
+    }
+    buffer
+}
+
+async fn stop_ccr(ccr_bin: &str, timeout: Duration) -> Result<()> {
+    status!("\r\nStopping CCR...");
+    
+    let mut child = ccr_command(ccr_bin, "stop")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
     
-    let status = child.wait().await?;
+    log::debug!("Spawned ccr stop");
+    let status = wait_with_timeout(&mut child, timeout, "ccr stop").await?;
     
     if status.success() {
-        println!("\r\nCCR stopped successfully");
+        log::info!("CCR stopped successfully");
     } else {
-        println!("\r\nWarning: CCR stop command exited with status: {}", status);
+        log::warn!("CCR stop command exited with status: {}", status);
     }
     
     Ok(())
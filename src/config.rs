@@ -1,7 +1,7 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use dirs::home_dir;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClaudeSettings {
@@ -15,20 +15,74 @@ pub struct ClaudeCodeRouterConfig {
     pub config: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ConfigItem {
     pub name: String,
     pub path: PathBuf,
+    #[serde(rename = "type")]
     pub config_type: ConfigType,
+    pub active: bool,
+    // The CodeRouter profile the last switch copied into config.json, while it still matches
+    pub loaded: bool,
+    // Listed first; see `pin`
+    pub pinned: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub modified: Option<std::time::SystemTime>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ConfigType {
     Claude,
+    #[serde(rename = "ccr")]
     CodeRouter,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Name,
+    #[default]
+    Type,
+    Mtime,
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(SortOrder::Name),
+            "type" => Ok(SortOrder::Type),
+            "mtime" => Ok(SortOrder::Mtime),
+            _ => anyhow::bail!("Unknown sort order: {}", s),
+        }
+    }
+}
+
+impl std::str::FromStr for ConfigType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "claude" => Ok(ConfigType::Claude),
+            "ccr" => Ok(ConfigType::CodeRouter),
+            _ => anyhow::bail!("Unknown configuration type: {}", s),
+        }
+    }
+}
+
 impl ConfigType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigType::Claude => "claude",
+            ConfigType::CodeRouter => "ccr",
+        }
+    }
+
     pub fn get_indicator(&self) -> &'static str {
         match self {
             ConfigType::Claude => "",
@@ -37,64 +91,1125 @@ impl ConfigType {
     }
 }
 
-pub fn load_configurations() -> Result<Vec<ConfigItem>> {
+#[derive(Debug, Clone)]
+pub struct ConfigDirs {
+    pub claude: PathBuf,
+    pub router: PathBuf,
+    // Additional read-only locations under $XDG_CONFIG_HOME, scanned after the ones above
+    pub xdg_claude: Option<PathBuf>,
+    pub xdg_router: Option<PathBuf>,
+}
+
+impl ConfigDirs {
+    // Precedence: explicit override (CLI flag), then CCD_CLAUDE_DIR / CCD_ROUTER_DIR, then
+    // CLAUDE_CONFIG_DIR (which the claude CLI reads itself) for the Claude side, then ~/.claude*
+    pub fn resolve(claude_override: Option<&str>, router_override: Option<&str>) -> Result<ConfigDirs> {
+        const CLAUDE_VARS: [&str; 2] = ["CCD_CLAUDE_DIR", "CLAUDE_CONFIG_DIR"];
+        const ROUTER_VARS: [&str; 1] = ["CCD_ROUTER_DIR"];
+        let claude_overridden = is_overridden(claude_override, &CLAUDE_VARS);
+        let router_overridden = is_overridden(router_override, &ROUTER_VARS);
+        Ok(ConfigDirs {
+            claude: resolve_dir(claude_override, &CLAUDE_VARS, ".claude")?,
+            router: resolve_dir(router_override, &ROUTER_VARS, ".claude-code-router")?,
+            xdg_claude: xdg_config_dir("claude").filter(|_| !claude_overridden),
+            xdg_router: xdg_config_dir("claude-code-router").filter(|_| !router_overridden),
+        })
+    }
+}
+
+fn is_overridden(override_value: Option<&str>, env_vars: &[&str]) -> bool {
+    override_value.is_some_and(|v| !v.is_empty()) || env_vars.iter().any(|var| std::env::var(var).is_ok_and(|v| !v.is_empty()))
+}
+
+// $XDG_CONFIG_HOME/<name>, defaulting to ~/.config as the spec says. Only on Linux and other
+// XDG platforms; Windows and macOS keep using just the home directory locations.
+fn xdg_config_dir(name: &str) -> Option<PathBuf> {
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        return None;
+    }
+
+    let base = std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| home_dir().map(|h| h.join(".config")))?;
+    Some(base.join(name))
+}
+
+// The first of `env_vars` that is set and non-empty wins
+fn resolve_dir(override_value: Option<&str>, env_vars: &[&str], default_name: &str) -> Result<PathBuf> {
+    if let Some(value) = override_value.filter(|v| !v.is_empty()) {
+        return expand_path(value);
+    }
+
+    match env_vars.iter().find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty())) {
+        Some(value) => expand_path(&value),
+        None => {
+            let home = home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+            Ok(home.join(default_name))
+        }
+    }
+}
+
+pub fn expand_path(raw: &str) -> Result<PathBuf> {
+    let path = if raw == "~" {
+        home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
+    } else if let Some(rest) = raw.strip_prefix("~/").or_else(|| raw.strip_prefix("~\\")) {
+        home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?.join(rest)
+    } else {
+        PathBuf::from(raw)
+    };
+
+    if path.is_absolute() {
+        Ok(path)
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+pub fn load_configurations(dirs: &ConfigDirs, sort: SortOrder) -> Result<Vec<ConfigItem>> {
+    load_configurations_counting_skipped(dirs, sort).map(|(configs, _)| configs)
+}
+
+// Also returns how many files or folders could not be read and were left out (each one is logged)
+pub fn load_configurations_counting_skipped(dirs: &ConfigDirs, sort: SortOrder) -> Result<(Vec<ConfigItem>, usize)> {
     let mut configs = Vec::new();
-    let home = home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
-
-    let claude_dir = home.join(".claude");
-    if claude_dir.exists() {
-        for entry in fs::read_dir(&claude_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-                if file_name.ends_with("-settings.json") {
-                    let name = file_name.strip_suffix("-settings.json").unwrap().to_string();
-                    configs.push(ConfigItem {
-                        name,
-                        path,
-                        config_type: ConfigType::Claude,
-                    });
+    let mut skipped = 0;
+
+    let claude_dir = &dirs.claude;
+    let claude_files = scan_dirs(claude_dir, dirs.xdg_claude.as_deref(), "-settings", &mut skipped);
+    // Per-file profiles come first, so they win when profiles.json uses the same name
+    for (name, path) in claude_files.into_iter().chain(profiles_file_entries(claude_dir, &mut skipped)) {
+        let error = cached_profile(&path).err();
+        let description = read_description(&path);
+        let modified = fs::metadata(profile_file(&path)).and_then(|m| m.modified()).ok();
+        configs.push(ConfigItem {
+            name,
+            path,
+            config_type: ConfigType::Claude,
+            active: false,
+            loaded: false,
+            pinned: false,
+            description,
+            error,
+            modified,
+        });
+    }
+
+    let router_dir = &dirs.router;
+    for (base_name, path) in scan_dirs(router_dir, dirs.xdg_router.as_deref(), "-config", &mut skipped) {
+        let name = format!("{}-ccr", base_name);
+        let error = cached_profile(&path).err();
+        let description = read_description(&path);
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        configs.push(ConfigItem {
+            name,
+            path,
+            config_type: ConfigType::CodeRouter,
+            active: false,
+            loaded: false,
+            pinned: false,
+            description,
+            error,
+            modified,
+        });
+    }
+
+    remove_duplicate_names(&mut configs);
+    // Pinned names that no longer match a profile are simply ignored
+    let state = crate::state::State::load(dirs);
+    for config in configs.iter_mut() {
+        config.pinned = state.pinned.contains(&config.name);
+    }
+    sort_configurations(&mut configs, sort);
+
+    mark_active_configurations(&mut configs, claude_dir, router_dir);
+    for config in configs.iter_mut() {
+        config.loaded = config.active
+            && config.config_type == ConfigType::CodeRouter
+            && state.router_profile.as_deref() == Some(config.name.as_str());
+    }
+    Ok((configs, skipped))
+}
+
+// Names as load_configurations would list them, from file names alone (used for shell completion)
+pub fn profile_names(dirs: &ConfigDirs) -> Vec<String> {
+    let mut skipped = 0;
+    let claude = scan_dirs(&dirs.claude, dirs.xdg_claude.as_deref(), "-settings", &mut skipped);
+    let router = scan_dirs(&dirs.router, dirs.xdg_router.as_deref(), "-config", &mut skipped);
+
+    let mut names = claude.into_iter()
+        .chain(profiles_file_entries(&dirs.claude, &mut skipped))
+        .map(|(name, _)| name)
+        .chain(router.into_iter().map(|(name, _)| format!("{}-ccr", name)))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// Explains an empty list: either there is nowhere to look yet, or the files are named differently
+pub fn no_configurations_hint(dirs: &ConfigDirs) -> String {
+    let exists = |primary: &Path, xdg: &Option<PathBuf>| primary.is_dir() || xdg.as_deref().is_some_and(Path::is_dir);
+    if !exists(&dirs.claude, &dirs.xdg_claude) && !exists(&dirs.router, &dirs.xdg_router) {
+        format!(
+            "Neither {} nor {} exists yet. Run `claude-codust new` (or `new --ccr`) to create a configuration.",
+            dirs.claude.display(),
+            dirs.router.display()
+        )
+    } else {
+        format!(
+            "No configuration files found in {} or {}. Claude configurations must be named <name>-settings.json \
+             and CodeRouter ones <name>-config.json (.yaml, .yml and .toml work too).",
+            dirs.claude.display(),
+            dirs.router.display()
+        )
+    }
+}
+
+// The primary directory first, then the XDG one unless both resolve to the same place
+// (e.g. ~/.config/claude symlinked to ~/.claude), so no file is listed twice
+fn scan_dirs(primary: &Path, xdg: Option<&Path>, suffix: &str, skipped: &mut usize) -> Vec<(String, PathBuf)> {
+    log::debug!("Scanning {} for {} files", primary.display(), suffix);
+    let mut found = find_profile_files(primary, suffix, skipped);
+
+    if let Some(xdg) = xdg.filter(|d| d.is_dir()) {
+        if fs::canonicalize(xdg).ok() != fs::canonicalize(primary).ok() {
+            log::debug!("Scanning {} for {} files", xdg.display(), suffix);
+            let seen = found.iter()
+                .filter_map(|(_, path)| fs::canonicalize(path).ok())
+                .collect::<Vec<_>>();
+            for (name, path) in find_profile_files(xdg, suffix, skipped) {
+                if !fs::canonicalize(&path).is_ok_and(|p| seen.contains(&p)) {
+                    found.push((name, path));
                 }
             }
         }
     }
 
-    let router_dir = home.join(".claude-code-router");
-    if router_dir.exists() {
-        for entry in fs::read_dir(&router_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-                if file_name.ends_with("-config.json") {
-                    let base_name = file_name.strip_suffix("-config.json").unwrap();
-                    let name = format!("{}-ccr", base_name);
-                    configs.push(ConfigItem {
-                        name,
-                        path,
-                        config_type: ConfigType::CodeRouter,
-                    });
+    found
+}
+
+// Profile files whose stem ends in `suffix` directly in `dir` or in one level of subfolders, named
+// by the stem without the suffix and prefixed with the folder, e.g. `work/prod-settings.yaml` -> `work/prod`.
+// Hidden folders are skipped; deeper nesting isn't scanned so large trees like ~/.claude/local stay cheap.
+fn find_profile_files(dir: &Path, suffix: &str, skipped: &mut usize) -> Vec<(String, PathBuf)> {
+    let mut found = Vec::new();
+    if !dir.exists() {
+        return found;
+    }
+
+    for entry in readable_entries(dir, skipped) {
+        let path = entry.path();
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()).map(|n| n.to_string()) else {
+            continue;
+        };
+        if is_hidden_or_temp_file(&file_name) {
+            continue;
+        }
+        let base_name = strip_profile_suffix(&file_name, suffix);
+
+        // Only a file that would be listed counts as skipped when it can't be inspected
+        if base_name.is_none() && path.is_dir() {
+            for sub_entry in readable_entries(&path, skipped) {
+                let sub_path = sub_entry.path();
+                let base_name = sub_path.file_name()
+                    .and_then(|n| n.to_str())
+                    .filter(|n| !is_hidden_or_temp_file(n))
+                    .and_then(|n| strip_profile_suffix(n, suffix))
+                    .map(|n| n.to_string());
+                let Some(base_name) = base_name else {
+                    continue;
+                };
+                if entry_type(&sub_entry, skipped).is_some_and(|t| !t.is_dir()) {
+                    found.push((format!("{}/{}", file_name, base_name), sub_path));
+                }
+            }
+        } else if let Some(base_name) = base_name {
+            if entry_type(&entry, skipped).is_some_and(|t| !t.is_dir()) {
+                found.push((base_name.to_string(), path));
+            }
+        }
+    }
+
+    found
+}
+
+// Dotfiles (including emacs `.#work-settings.json` locks, vim `.work-settings.json.swp` and the
+// temporary files write_atomic renames into place) and other editor leftovers such as
+// `work-settings.json~` or `#work-settings.json#`, which would otherwise look like profiles
+fn is_hidden_or_temp_file(file_name: &str) -> bool {
+    const TEMP_EXTENSIONS: [&str; 7] = ["swp", "swo", "swx", "tmp", "bak", "orig", "rej"];
+    file_name.starts_with('.')
+        || file_name.starts_with("~$")
+        || file_name.ends_with('~')
+        || (file_name.starts_with('#') && file_name.ends_with('#'))
+        || file_name.rsplit_once('.').is_some_and(|(_, extension)| TEMP_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+// Claude profiles may also be kept together in one file, profiles.json in the Claude directory,
+// mapping names to settings objects. Each entry is given a path like `profiles.json#work`, which
+// the read functions below resolve to the entry; the `#` part is the in-file pointer.
+pub const PROFILES_FILE: &str = "profiles.json";
+
+fn profiles_file_entries(dir: &Path, skipped: &mut usize) -> Vec<(String, PathBuf)> {
+    let path = dir.join(PROFILES_FILE);
+    if !path.is_file() {
+        return Vec::new();
+    }
+    let entries = match crate::cache::parse_profile(&path) {
+        Ok(serde_json::Value::Object(entries)) => entries,
+        Ok(_) => {
+            log::warn!("Skipping {}: expected an object mapping profile names to settings", path.display());
+            *skipped += 1;
+            return Vec::new();
+        }
+        Err(e) => {
+            log::warn!("Skipping {}: {}", path.display(), e);
+            *skipped += 1;
+            return Vec::new();
+        }
+    };
+
+    entries.keys()
+        .filter(|name| {
+            // The name ends up in the synthetic file name, so it can't contain a path separator
+            let valid = !name.is_empty() && !name.contains(['/', '\\']);
+            if !valid {
+                log::warn!("Skipping '{}' in {}: profile names there can't be empty or contain / or \\", name, path.display());
+            }
+            valid
+        })
+        .map(|name| (name.clone(), dir.join(format!("{}#{}", PROFILES_FILE, name))))
+        .collect()
+}
+
+// For an entry of profiles.json, the file and the entry's name
+pub fn profile_entry(path: &Path) -> Option<(PathBuf, &str)> {
+    let name = path.file_name()?.to_str()?.strip_prefix(PROFILES_FILE)?.strip_prefix('#')?;
+    Some((path.with_file_name(PROFILES_FILE), name))
+}
+
+// The file on disk that holds the profile at `path`
+pub fn profile_file(path: &Path) -> PathBuf {
+    profile_entry(path).map_or_else(|| path.to_path_buf(), |(file, _)| file)
+}
+
+pub fn profiles_file_entry(profiles: &serde_json::Value, name: &str, file: &Path) -> Result<serde_json::Value> {
+    match profiles.get(name) {
+        Some(value) if value.is_object() => Ok(value.clone()),
+        Some(_) => anyhow::bail!("'{}' in {} is not an object", name, file.display()),
+        None => anyhow::bail!("{} has no profile named '{}'", file.display(), name),
+    }
+}
+
+// A directory or entry that can't be read is logged and counted instead of failing the whole scan
+fn readable_entries(dir: &Path, skipped: &mut usize) -> Vec<fs::DirEntry> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Skipping {}: {}", dir.display(), e);
+            *skipped += 1;
+            return Vec::new();
+        }
+    };
+
+    entries
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                log::warn!("Skipping an entry of {}: {}", dir.display(), e);
+                *skipped += 1;
+                None
+            }
+        })
+        .collect()
+}
+
+// Follows symlinks like Path::is_dir, but reports why an entry couldn't be inspected
+fn entry_type(entry: &fs::DirEntry, skipped: &mut usize) -> Option<fs::FileType> {
+    match fs::metadata(entry.path()) {
+        Ok(metadata) => Some(metadata.file_type()),
+        Err(e) => {
+            log::warn!("Skipping {}: {}", entry.path().display(), e);
+            *skipped += 1;
+            None
+        }
+    }
+}
+
+pub const PROFILE_EXTENSIONS: [&str; 4] = ["json", "yaml", "yml", "toml"];
+
+// `work-settings.yaml` with suffix `-settings` -> `work`; None for unsupported extensions
+pub fn strip_profile_suffix<'a>(file_name: &'a str, suffix: &str) -> Option<&'a str> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    if !PROFILE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+        return None;
+    }
+    stem.strip_suffix(suffix).filter(|name| !name.is_empty())
+}
+
+fn profile_extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default()
+}
+
+// Profiles may be JSON, YAML or TOML; all of them end up as the same JSON value
+pub fn parse_profile_str(path: &Path, content: &str) -> Result<serde_json::Value> {
+    let content = strip_bom(content);
+    Ok(match profile_extension(path).as_str() {
+        "yaml" | "yml" => serde_yaml::from_str(content)?,
+        "toml" => toml::from_str(content)?,
+        _ => serde_json::from_str(content)?,
+    })
+}
+
+// Editors on Windows like to start files with a UTF-8 BOM, which serde_json rejects
+pub fn strip_bom(content: &str) -> &str {
+    content.strip_prefix('\u{feff}').unwrap_or(content)
+}
+
+// The profile as it is applied, with any `extends` base merged in
+pub fn read_profile(path: &Path) -> Result<serde_json::Value> {
+    resolve_extends(path, read_profile_raw(path)?, &read_profile_raw, &mut Vec::new())
+}
+
+// Just this file, `extends` left as written; for commands that edit the file itself.
+// Errors name the file, since they may come from settings.json or a base profile.
+pub fn read_profile_raw(path: &Path) -> Result<serde_json::Value> {
+    if let Some((file, name)) = profile_entry(path) {
+        return profiles_file_entry(&read_profile_raw(&file)?, name, &file);
+    }
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_profile_str(path, &content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+// Like read_profile, but every file in the chain comes from the parse cache. Errors in the
+// profile itself are shown next to its path already; only those from a base name the file.
+pub fn cached_profile(path: &Path) -> Result<serde_json::Value, String> {
+    let read_base = |path: &Path| {
+        crate::cache::parse_profile(path).map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))
+    };
+    resolve_extends(path, crate::cache::parse_profile(path)?, &read_base, &mut Vec::new()).map_err(|e| format!("{:#}", e))
+}
+
+// `"extends": "base"` puts the profile on top of base-settings.* (or base-config.* for a
+// CodeRouter profile) from the same directory; bases may extend further bases
+fn resolve_extends(
+    path: &Path,
+    value: serde_json::Value,
+    read: &dyn Fn(&Path) -> Result<serde_json::Value>,
+    chain: &mut Vec<PathBuf>,
+) -> Result<serde_json::Value> {
+    let Some(extends) = value.get("extends") else {
+        return Ok(value);
+    };
+    let Some(base) = extends.as_str().filter(|b| !b.is_empty()) else {
+        anyhow::bail!("extends in {} must be the name of another profile", path.display());
+    };
+
+    chain.push(path.to_path_buf());
+    let base_path = extends_path(path, base)?;
+    if chain.contains(&base_path) {
+        let names = chain.iter()
+            .chain(std::iter::once(&base_path))
+            .filter_map(|p| p.file_name())
+            .map(|n| n.to_string_lossy())
+            .collect::<Vec<_>>();
+        anyhow::bail!("extends cycle: {}", names.join(" -> "));
+    }
+    let base_value = resolve_extends(&base_path, read(&base_path)?, read, chain)?;
+
+    let mut merged = base_value;
+    merge_profile(&mut merged, value);
+    if let Some(obj) = merged.as_object_mut() {
+        obj.shift_remove("extends");
+    }
+    Ok(merged)
+}
+
+fn extends_path(path: &Path, base: &str) -> Result<PathBuf> {
+    // Entries of profiles.json look for the base among the other entries first, per-file
+    // Claude profiles look there after the files next to them
+    if let Some((file, _)) = profile_entry(path) {
+        let has_base = crate::cache::parse_profile(&file).is_ok_and(|profiles| profiles.get(base).is_some());
+        if has_base {
+            return Ok(file.with_file_name(format!("{}#{}", PROFILES_FILE, base)));
+        }
+    }
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let suffix = if strip_profile_suffix(file_name, "-config").is_some() { "-config" } else { "-settings" };
+    // CodeRouter profiles are listed with -ccr, so accept the base under either name
+    let base = if suffix == "-config" { base.strip_suffix("-ccr").unwrap_or(base) } else { base };
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let in_profiles_file = || {
+        let file = dir.join(PROFILES_FILE);
+        let has_base = suffix == "-settings"
+            && crate::cache::parse_profile(&file).is_ok_and(|profiles| profiles.get(base).is_some());
+        has_base.then(|| dir.join(format!("{}#{}", PROFILES_FILE, base)))
+    };
+    PROFILE_EXTENSIONS.iter()
+        .map(|ext| dir.join(format!("{}{}.{}", base, suffix, ext)))
+        .find(|candidate| candidate.is_file())
+        .or_else(in_profiles_file)
+        .ok_or_else(|| anyhow::anyhow!("{} extends '{}', but there is no {}{}.* next to it", file_name, base, base, suffix))
+}
+
+// Objects are merged key by key; any other value in `top` replaces the one in `base`
+fn merge_profile(base: &mut serde_json::Value, top: serde_json::Value) {
+    match (base, top) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(top)) => {
+            for (key, value) in top {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_profile(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, top) => *base = top,
+    }
+}
+
+// Writes `value` back in the format the file's extension calls for
+pub fn write_profile(path: &Path, value: &serde_json::Value) -> Result<()> {
+    let content = match profile_extension(path).as_str() {
+        "yaml" | "yml" => serde_yaml::to_string(value)?,
+        "toml" => toml::to_string_pretty(value)?,
+        _ => serde_json::to_string_pretty(value)?,
+    };
+    let crlf = fs::read_to_string(path).is_ok_and(|previous| previous.contains("\r\n"));
+    write_atomic(path, normalize_line_endings(&content, crlf).as_bytes())
+}
+
+// Turns a directory or read-only file where a switch has to write into an actionable error
+// instead of the bare OS error the write would fail with. Missing files are fine.
+pub fn ensure_replaceable(path: &Path, check_writable: bool) -> Result<()> {
+    let Ok(metadata) = fs::metadata(path) else {
+        return Ok(());
+    };
+    if metadata.is_dir() {
+        anyhow::bail!("{} is a directory, not a file. Move or remove it and try again", path.display());
+    }
+    if !metadata.is_file() {
+        anyhow::bail!("{} is not a regular file. Move or remove it and try again", path.display());
+    }
+    // Opening for append tests the real permissions (owner, group, ACLs) without changing the file
+    if check_writable {
+        if let Err(e) = fs::OpenOptions::new().append(true).open(path) {
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                anyhow::bail!(
+                    "{} is read-only. Make it writable (e.g. `chmod u+w {}`) and try again",
+                    path.display(),
+                    path.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// Writes a temporary file next to `path` and renames it into place, so claude or ccr never
+// read a half-written file. A symlinked `path` is followed, replacing the file it points to.
+// The file keeps its permissions (a `chmod 600` on a file holding keys survives a switch);
+// new files are only readable by the user.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let file_name = path.file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid file name: {}", path.display()))?;
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+    let permissions = fs::metadata(&path).ok().map(|m| m.permissions());
+
+    let result = create_private_file(&temp_path)
+        .and_then(|mut file| {
+            if let Some(permissions) = permissions {
+                file.set_permissions(permissions)?;
+            }
+            std::io::Write::write_all(&mut file, content)?;
+            file.sync_all()
+        })
+        .and_then(|()| replace_file(&temp_path, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    Ok(result?)
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+#[cfg(not(windows))]
+fn replace_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    fs::rename(source, target)
+}
+
+// rename replaces an existing file on Windows too, but fails while another process has the
+// target open without FILE_SHARE_DELETE; removing it first is the best that can be done then
+#[cfg(windows)]
+fn replace_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    fs::rename(source, target).or_else(|_| {
+        fs::remove_file(target)?;
+        fs::rename(source, target)
+    })
+}
+
+// Every line ends the same way: CRLF when the file being replaced used it, LF otherwise
+fn normalize_line_endings(content: &str, crlf: bool) -> String {
+    let content = content.replace("\r\n", "\n");
+    if crlf {
+        content.replace('\n', "\r\n")
+    } else {
+        content
+    }
+}
+
+// The file a profile called `name` lives in, the inverse of find_profile_files. CodeRouter names
+// may be given with or without the displayed `-ccr` suffix; one subfolder level is allowed.
+pub fn profile_path(dirs: &ConfigDirs, config_type: ConfigType, name: &str) -> Result<PathBuf> {
+    let base_name = match config_type {
+        ConfigType::Claude => name,
+        ConfigType::CodeRouter => name.strip_suffix("-ccr").unwrap_or(name),
+    };
+
+    let parts = base_name.split('/').collect::<Vec<_>>();
+    let valid = parts.len() <= 2
+        && parts.iter().all(|p| !p.is_empty() && *p != "." && *p != ".." && !p.starts_with('.') && !p.contains('\\'));
+    if !valid {
+        anyhow::bail!("Invalid profile name: '{}'", name);
+    }
+
+    Ok(match config_type {
+        ConfigType::Claude => dirs.claude.join(format!("{}-settings.json", base_name)),
+        ConfigType::CodeRouter => dirs.router.join(format!("{}-config.json", base_name)),
+    })
+}
+
+// A Claude profile named e.g. `foo-ccr-settings.json` collides with the CodeRouter profile
+// `foo-config.json`. Claude profiles are scanned first, so they win and the later file is skipped;
+// likewise ~/.claude* wins over the XDG directories.
+fn remove_duplicate_names(configs: &mut Vec<ConfigItem>) {
+    let mut seen: HashMap<String, PathBuf> = HashMap::new();
+    configs.retain(|config| match seen.get(&config.name) {
+        Some(kept) => {
+            log::warn!(
+                "Skipping {} because its name '{}' is already used by {}",
+                config.path.display(),
+                config.name,
+                kept.display()
+            );
+            false
+        }
+        None => {
+            seen.insert(config.name.clone(), config.path.clone());
+            true
+        }
+    });
+}
+
+// Returns a description of the problem if the file can't be read or doesn't parse
+pub fn validate_config_file(path: &Path) -> Option<String> {
+    read_profile(path).err().map(|e| format!("{:#}", e))
+}
+
+fn sort_configurations(configs: &mut [ConfigItem], sort: SortOrder) {
+    match sort {
+        SortOrder::Name => configs.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortOrder::Type => configs.sort_by(|a, b| {
+            match (&a.config_type, &b.config_type) {
+                (ConfigType::Claude, ConfigType::CodeRouter) => std::cmp::Ordering::Less,
+                (ConfigType::CodeRouter, ConfigType::Claude) => std::cmp::Ordering::Greater,
+                _ => a.name.cmp(&b.name),
+            }
+        }),
+        // Newest first; files whose mtime can't be read sort last
+        SortOrder::Mtime => configs.sort_by_key(|c| std::cmp::Reverse(c.modified.unwrap_or(std::time::UNIX_EPOCH))),
+    }
+
+    // Stable, so pinned profiles keep the order above among themselves, as do the rest
+    match sort {
+        SortOrder::Type => configs.sort_by_key(|c| (c.config_type == ConfigType::CodeRouter, !c.pinned)),
+        SortOrder::Name | SortOrder::Mtime => configs.sort_by_key(|c| !c.pinned),
+    }
+}
+
+// Optional free-text label stored in the profile itself; claude and ccr ignore the key
+fn read_description(path: &Path) -> Option<String> {
+    crate::cache::parse_profile(path).ok()?
+        .get("description")?
+        .as_str()
+        .filter(|d| !d.is_empty())
+        .map(|d| d.to_string())
+}
+
+pub fn read_json_file(path: &Path) -> Option<serde_json::Value> {
+    read_profile(path).ok()
+}
+
+// A CodeRouter profile is active when its contents match the live config.json; a Claude
+// profile is active when its env block matches the env block of settings.json. Profiles
+// with identical contents are all marked.
+fn mark_active_configurations(configs: &mut [ConfigItem], claude_dir: &Path, router_dir: &Path) {
+    let router_config = read_json_file(&router_dir.join("config.json"));
+    let settings_env = read_json_file(&claude_dir.join("settings.json"))
+        .and_then(|s| s.get("env").cloned());
+
+    for config in configs.iter_mut() {
+        let Ok(profile) = cached_profile(&config.path) else {
+            continue;
+        };
+
+        config.active = match config.config_type {
+            ConfigType::CodeRouter => router_config.as_ref() == Some(&profile),
+            ConfigType::Claude => match (profile.get("env"), settings_env.as_ref()) {
+                (Some(profile_env), Some(settings_env)) => profile_env == settings_env,
+                _ => false,
+            },
+        };
+    }
+}
+
+// Describes a live config.json that none of the CodeRouter profiles match, which happens after
+// it is edited by hand. None when there is no readable config.json or a profile matches it.
+pub fn unmatched_router_config(dirs: &ConfigDirs, configs: &[ConfigItem]) -> Option<String> {
+    let path = dirs.router.join("config.json");
+    read_json_file(&path)?;
+    if configs.iter().any(|c| c.config_type == ConfigType::CodeRouter && c.active) {
+        return None;
+    }
+    let note = match crate::state::State::load(dirs).router_profile {
+        Some(name) => format!("{} does not match any profile, it was edited after {} was loaded", path.display(), name),
+        None => format!("{} does not match any profile", path.display()),
+    };
+    Some(note)
+}
+
+pub const DEFAULT_ROUTER_PORT: &str = "3456";
+
+// PORT may be written as either "3456" or 3456; anything else is passed through as written so
+// parse_router_port can report it
+pub fn router_port(config: &serde_json::Value) -> String {
+    match config.get("PORT") {
+        Some(serde_json::Value::String(port)) => port.clone(),
+        None | Some(serde_json::Value::Null) => DEFAULT_ROUTER_PORT.to_string(),
+        Some(port) => port.to_string(),
+    }
+}
+
+pub fn parse_router_port(config: &serde_json::Value) -> Result<u16> {
+    let port = router_port(config);
+    match port.trim().parse::<u16>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => anyhow::bail!("Invalid PORT '{}' in CodeRouter config, expected a number from 1 to 65535", port),
+    }
+}
+
+// Problems that would make a profile misbehave once launched; empty when it looks fine.
+// CodeRouter: PORT must be a port number and APIKEY, if set, a non-empty string.
+// Both: apiKeyFile, if set, must be a path and env must map names to strings, numbers,
+// booleans or null.
+pub fn check_profile(config: &serde_json::Value, config_type: ConfigType) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !config.is_object() {
+        problems.push("not an object".to_string());
+        return problems;
+    }
+
+    match config_type {
+        ConfigType::CodeRouter => {
+            match config.get("APIKEY") {
+                None => {}
+                Some(serde_json::Value::String(key)) if !key.is_empty() => {}
+                Some(_) => problems.push("APIKEY is empty or not a string".to_string()),
+            }
+            // Also reports an invalid PORT
+            if let Err(e) = router_base_url(config) {
+                problems.push(e.to_string());
+            }
+        }
+        ConfigType::Claude => {}
+    }
+
+    match config.get("apiKeyFile") {
+        None => {}
+        Some(serde_json::Value::String(file)) if !file.is_empty() => {}
+        Some(_) => problems.push("apiKeyFile is empty or not a string".to_string()),
+    }
+
+    match config.get("env") {
+        None => {}
+        Some(serde_json::Value::Object(env)) => {
+            for (key, value) in env {
+                if value.is_object() || value.is_array() {
+                    problems.push(format!("env.{} is not a string", key));
                 }
             }
         }
+        Some(_) => problems.push("env is not an object".to_string()),
+    }
+
+    problems
+}
+
+// The optional HOST key, defaulting to 127.0.0.1. An IPv6 address is returned without the
+// brackets it may be written with; a port belongs in PORT, so any other `:` is rejected.
+pub fn router_host(config: &serde_json::Value) -> Result<&str> {
+    let host = config.get("HOST").and_then(|h| h.as_str()).unwrap_or("127.0.0.1");
+    let unbracketed = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    if unbracketed.parse::<std::net::Ipv6Addr>().is_ok() {
+        return Ok(unbracketed);
+    }
+    if host.is_empty() || host.contains(|c: char| c.is_whitespace() || "/?#@:[]".contains(c)) {
+        anyhow::bail!("Invalid HOST '{}' in CodeRouter config, expected a host name or IP address without a port", host);
+    }
+    Ok(host)
+}
+
+// Builds ANTHROPIC_BASE_URL from the optional SCHEME and HOST keys plus PORT
+pub fn router_base_url(config: &serde_json::Value) -> Result<String> {
+    let scheme = config.get("SCHEME").and_then(|s| s.as_str()).unwrap_or("http");
+
+    if scheme != "http" && scheme != "https" {
+        anyhow::bail!("Invalid SCHEME '{}' in CodeRouter config, expected http or https", scheme);
+    }
+    let host = router_host(config)?;
+    let port = parse_router_port(config)?;
+
+    if host.contains(':') {
+        Ok(format!("{}://[{}]:{}", scheme, host, port))
+    } else {
+        Ok(format!("{}://{}:{}", scheme, host, port))
+    }
+}
+
+// The variables a profile adds on top of the inherited environment when claude is launched
+fn build_env(config: &serde_json::Value, config_type: &ConfigType) -> Result<HashMap<String, String>> {
+    let mut profile_env = HashMap::new();
+    match config_type {
+        ConfigType::Claude => {
+            if let Some(api_key) = read_api_key_file(config)? {
+                profile_env.insert("ANTHROPIC_API_KEY".to_string(), api_key);
+            }
+        }
+        ConfigType::CodeRouter => {
+            if let Some(api_key) = config.get("APIKEY").and_then(|k| k.as_str()) {
+                profile_env.insert("ANTHROPIC_API_KEY".to_string(), expand_env_refs(api_key, "APIKEY")?);
+            } else if let Some(api_key) = read_api_key_file(config)? {
+                profile_env.insert("ANTHROPIC_API_KEY".to_string(), api_key);
+            } else {
+                profile_env.insert("ANTHROPIC_AUTH_TOKEN".to_string(), "test".to_string());
+            }
+
+            let base_url = router_base_url(config)?;
+            if parse_router_port(config)? < 1024 {
+                log::warn!("PORT {} is below 1024, CCR usually cannot listen on it without root", router_port(config));
+            }
+            profile_env.insert("ANTHROPIC_BASE_URL".to_string(), base_url);
+        }
+    }
+
+    // Applied after the derived CodeRouter variables so a router profile's env can override them
+    if let Some(env_obj) = config.get("env").and_then(|e| e.as_object()) {
+        for (key, value) in env_obj {
+            if let Some(value_str) = env_value_string(key, value) {
+                profile_env.insert(key.clone(), expand_env_refs(&value_str, &format!("env.{}", key))?);
+            }
+        }
     }
+    Ok(profile_env)
+}
+
+// Replaces each ${env:NAME} with that variable, so keys can live outside the profile
+fn expand_env_refs(value: &str, field: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${env:") {
+        let Some(len) = rest[start..].find('}') else {
+            anyhow::bail!("{} has an unterminated ${{env:...}} reference", field);
+        };
+        let name = &rest[start + 6..start + len];
+        let resolved = std::env::var(name)
+            .map_err(|_| anyhow::anyhow!("{} refers to ${{env:{}}}, but {} is not set", field, name, name))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&resolved);
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
 
-    configs.sort_by(|a, b| {
-        match (&a.config_type, &b.config_type) {
-            (ConfigType::Claude, ConfigType::CodeRouter) => std::cmp::Ordering::Less,
-            (ConfigType::CodeRouter, ConfigType::Claude) => std::cmp::Ordering::Greater,
-            _ => a.name.cmp(&b.name),
+// The key from the file named by apiKeyFile, trimmed; None when the profile has no apiKeyFile
+fn read_api_key_file(config: &serde_json::Value) -> Result<Option<String>> {
+    let Some(file) = config.get("apiKeyFile").and_then(|f| f.as_str()) else {
+        return Ok(None);
+    };
+    let path = expand_path(file)?;
+    let key = fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("Cannot read apiKeyFile {}: {}", path.display(), e))?;
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("apiKeyFile {} is empty", path.display());
+    }
+    Ok(Some(key.to_string()))
+}
+
+// Numbers and booleans are valid env values too; nested values are passed on as JSON
+fn env_value_string(key: &str, value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        serde_json::Value::Null => None,
+        serde_json::Value::Object(_) | serde_json::Value::Array(_) => {
+            log::warn!("Env value for {} is not a scalar, passing it as JSON", key);
+            Some(value.to_string())
         }
+    }
+}
+
+// How profile variables combine with the inherited environment
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum EnvMode {
+    // The profile wins over inherited variables
+    #[default]
+    Overlay,
+    // Inherited variables win; the profile only fills in what is missing
+    Underlay,
+}
+
+impl std::str::FromStr for EnvMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "overlay" => Ok(EnvMode::Overlay),
+            "underlay" => Ok(EnvMode::Underlay),
+            _ => anyhow::bail!("Unknown env mode: {}", s),
+        }
+    }
+}
+
+// The full environment for claude: `base` (normally the inherited environment) combined with
+// the profile's variables according to `mode`
+pub fn build_launch_env(
+    config: &serde_json::Value,
+    config_type: &ConfigType,
+    base: HashMap<String, String>,
+    mode: EnvMode,
+) -> Result<HashMap<String, String>> {
+    let mut env_vars = base;
+    for (key, value) in build_env(config, config_type)? {
+        match mode {
+            EnvMode::Overlay => {
+                env_vars.insert(key, value);
+            }
+            EnvMode::Underlay => {
+                env_vars.entry(key).or_insert(value);
+            }
+        }
+    }
+    Ok(env_vars)
+}
+
+// Drops inherited ANTHROPIC_* variables a Claude profile doesn't set itself, so a stale shell
+// export can't point claude at another endpoint. CodeRouter profiles always set their own.
+pub fn strip_inherited_anthropic_vars(base: &mut HashMap<String, String>, config: &serde_json::Value) {
+    let profile_env = config.get("env").and_then(|e| e.as_object());
+    base.retain(|key, _| {
+        !key.starts_with("ANTHROPIC_") || profile_env.is_some_and(|env| env.contains_key(key))
     });
-    Ok(configs)
 }
 
-pub fn backup_settings_json_if_exists(home: &PathBuf, config_path: &PathBuf) -> Result<()> {
-    let claude_dir = home.join(".claude");
+const SECRET_KEYS: [&str; 3] = ["APIKEY", "ANTHROPIC_API_KEY", "ANTHROPIC_AUTH_TOKEN"];
+
+pub fn is_secret_key(key: &str) -> bool {
+    let key = key.to_uppercase();
+    SECRET_KEYS.contains(&key.as_str()) || key.ends_with("_KEY") || key.ends_with("_TOKEN")
+}
+
+// Keeps the last 4 characters so similar keys can still be told apart
+pub fn mask_value(value: &str) -> String {
+    let chars = value.chars().collect::<Vec<_>>();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let tail = chars[chars.len() - 4..].iter().collect::<String>();
+    format!("****{}", tail)
+}
+
+pub fn mask_secrets(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(obj) => {
+            let mut masked = serde_json::Map::new();
+            for (key, value) in obj {
+                let value = match value {
+                    serde_json::Value::String(s) if is_secret_key(key) => serde_json::Value::String(mask_value(s)),
+                    serde_json::Value::Object(_) | serde_json::Value::Array(_) => mask_secrets(value),
+                    _ if is_secret_key(key) => serde_json::Value::String("****".to_string()),
+                    _ => value.clone(),
+                };
+                masked.insert(key.clone(), value);
+            }
+            serde_json::Value::Object(masked)
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.iter().map(mask_secrets).collect())
+        }
+        _ => value.clone(),
+    }
+}
+
+// Masks secrets in a profile's text as written (JSON, YAML or TOML) by replacing each secret
+// string found in its parsed `value`, so comments and layout survive
+pub fn mask_secrets_in_text(text: &str, value: &serde_json::Value) -> String {
+    let mut secrets = Vec::new();
+    collect_secrets(value, &mut secrets);
+    // Longest first, so a secret that contains another is replaced whole
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    secrets.dedup();
+    secrets.iter().fold(text.to_string(), |text, secret| replace_whole(&text, secret, &mask_value(secret)))
+}
+
+// Replaces `needle` only where it isn't part of a longer word, so a short secret like "abc"
+// leaves key names and other values alone
+fn replace_whole(text: &str, needle: &str, replacement: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let mut result = String::new();
+    let mut copied = 0;
+    for (index, _) in text.match_indices(needle) {
+        let end = index + needle.len();
+        if text[..index].chars().next_back().is_some_and(is_word) || text[end..].chars().next().is_some_and(is_word) {
+            continue;
+        }
+        result.push_str(&text[copied..index]);
+        result.push_str(replacement);
+        copied = end;
+    }
+    result.push_str(&text[copied..]);
+    result
+}
+
+fn collect_secrets(value: &serde_json::Value, secrets: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(obj) => {
+            for (key, value) in obj {
+                match value {
+                    serde_json::Value::String(s) if is_secret_key(key) && !s.is_empty() => secrets.push(s.clone()),
+                    _ => collect_secrets(value, secrets),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter().for_each(|item| collect_secrets(item, secrets)),
+        _ => {}
+    }
+}
+
+// One key that differs between two profiles; nested objects are compared key by key and
+// named by their dotted path, e.g. `env.ANTHROPIC_MODEL`
+#[derive(Debug, PartialEq)]
+pub enum ProfileChange {
+    Added(String, serde_json::Value),
+    Removed(String, serde_json::Value),
+    Changed(String, serde_json::Value, serde_json::Value),
+}
+
+pub fn diff_profiles(old: &serde_json::Value, new: &serde_json::Value) -> Vec<ProfileChange> {
+    let mut changes = Vec::new();
+    diff_values("", old, new, &mut changes);
+    changes
+}
+
+fn diff_values(path: &str, old: &serde_json::Value, new: &serde_json::Value, changes: &mut Vec<ProfileChange>) {
+    match (old.as_object(), new.as_object()) {
+        (Some(old_obj), Some(new_obj)) => {
+            let mut keys = old_obj.keys().chain(new_obj.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let key_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                match (old_obj.get(key), new_obj.get(key)) {
+                    (Some(old_value), Some(new_value)) => diff_values(&key_path, old_value, new_value, changes),
+                    (Some(old_value), None) => changes.push(ProfileChange::Removed(key_path, old_value.clone())),
+                    (None, Some(new_value)) => changes.push(ProfileChange::Added(key_path, new_value.clone())),
+                    (None, None) => {}
+                }
+            }
+        }
+        _ if old != new => changes.push(ProfileChange::Changed(path.to_string(), old.clone(), new.clone())),
+        _ => {}
+    }
+}
+
+pub fn find_configuration(dirs: &ConfigDirs, name: &str) -> Result<ConfigItem> {
+    let mut configs = load_configurations(dirs, SortOrder::default())?;
+
+    if configs.is_empty() {
+        anyhow::bail!("{}", no_configurations_hint(dirs));
+    }
+
+    if let Some(index) = configs.iter().position(|c| c.name == name) {
+        return Ok(configs.swap_remove(index));
+    }
+
+    let available = configs.iter()
+        .map(|c| c.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    anyhow::bail!("No configuration named '{}'. Available: {}", name, available)
+}
+
+pub fn local_settings_path() -> Result<PathBuf> {
+    Ok(std::env::current_dir()?.join(".claude").join("settings.local.json"))
+}
+
+// Keys this tool reads itself; they never end up in settings.local.json
+const TOOL_KEYS: [&str; 5] = ["env", "description", "preLaunch", "postLaunch", "apiKeyFile"];
+
+// A hook is a single command or a list of commands run in order
+pub fn launch_hooks(config: &serde_json::Value, key: &str) -> Vec<String> {
+    match config.get(key) {
+        Some(serde_json::Value::String(command)) => vec![command.clone()],
+        Some(serde_json::Value::Array(commands)) => commands.iter()
+            .filter_map(|c| c.as_str())
+            .map(|c| c.to_string())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Everything in a Claude profile except `env` and our own keys, or None when that
+// leaves nothing to write
+pub fn local_settings(config: &serde_json::Value) -> Option<serde_json::Value> {
+    let local = config.as_object()?
+        .iter()
+        .filter(|(key, _)| !TOOL_KEYS.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect::<serde_json::Map<_, _>>();
+
+    if local.is_empty() {
+        None
+    } else {
+        Some(serde_json::Value::Object(local))
+    }
+}
+
+pub fn backup_settings_json_if_exists(claude_dir: &Path, config_path: &Path, backup_dir: &Path, backup_count: usize) -> Result<()> {
     let settings_path = claude_dir.join("settings.json");
 
     if settings_path.exists() {
-        let content = fs::read_to_string(&settings_path)?;
-        let mut config: serde_json::Value = serde_json::from_str(&content)?;
+        let mut config = read_profile_raw(&settings_path)?;
         
         // Check if config has env key and remove specific ANTHROPIC keys
         if let Some(env_obj) = config.get_mut("env").and_then(|e| e.as_object_mut()) {
@@ -102,58 +1217,457 @@ pub fn backup_settings_json_if_exists(home: &PathBuf, config_path: &PathBuf) ->
             let mut removed_keys = Vec::new();
             
             for key in anthropic_keys {
-                if env_obj.remove(key).is_some() {
+                if env_obj.shift_remove(key).is_some() {
                     removed_keys.push(key);
                 }
             }
             
             if !removed_keys.is_empty() {
-                println!("\r\nRemoved API keys from settings.json env: {:?}", removed_keys);
+                // Only now, a read-only settings.json that needs no changes is fine
+                ensure_replaceable(&settings_path, true)?;
+                log::debug!("Rewriting {}", settings_path.display());
+                crate::backup::create_backup(&settings_path, backup_dir, backup_count)?;
+                log::info!("Removed API keys from settings.json env: {:?}", removed_keys);
                 
                 // If env object is now empty, remove the entire env key
                 if env_obj.is_empty() {
                     if let Some(obj) = config.as_object_mut() {
-                        obj.remove("env");
-                        println!("\r\nRemoved empty 'env' key from settings.json");
+                        obj.shift_remove("env");
+                        log::info!("Removed empty 'env' key from settings.json");
                     }
                 }
                 
                 // Write back the modified config
-                let updated_content = serde_json::to_string_pretty(&config)?;
-                fs::write(&settings_path, updated_content)?;
+                write_profile(&settings_path, &config)?;
             }
         }
     }
 
     // Extract non-env keys to local settings
-    let config_content = fs::read_to_string(config_path)?;
-    let config: serde_json::Value = serde_json::from_str(&config_content)?;
-    
-    if let Some(obj) = config.as_object() {
-        // Create a new object with all keys except 'env'
-        let mut local_settings = serde_json::Map::new();
-        for (key, value) in obj {
-            if key != "env" {
-                local_settings.insert(key.clone(), value.clone());
-            }
+    let config = read_profile(config_path)?;
+
+    if let Some(local_config) = local_settings(&config) {
+        let local_settings_path = local_settings_path()?;
+
+        // Create .claude directory if it doesn't exist
+        if let Some(parent) = local_settings_path.parent() {
+            fs::create_dir_all(parent)?;
         }
-        
-        // Only write if there are non-env keys
-        if !local_settings.is_empty() {
-            let current_dir = std::env::current_dir()?;
-            let local_claude_dir = current_dir.join(".claude");
-            
-            // Create .claude directory if it doesn't exist
-            fs::create_dir_all(&local_claude_dir)?;
-            
-            let local_settings_path = local_claude_dir.join("settings.local.json");
-            let local_config = serde_json::Value::Object(local_settings);
-            let local_content = serde_json::to_string_pretty(&local_config)?;
-            
-            fs::write(&local_settings_path, local_content)?;
-            println!("\r\nCreated local settings at: {}", local_settings_path.display());
+
+        // Keep a hand-maintained file recoverable instead of silently overwriting it
+        let existing = read_json_file(&local_settings_path);
+        if local_settings_path.exists() && existing.as_ref() != Some(&local_config) {
+            let backup_path = local_settings_path.with_extension("json.bak");
+            fs::copy(&local_settings_path, &backup_path)?;
+            log::warn!("Saved the existing {} to {}", local_settings_path.display(), backup_path.display());
         }
+
+        write_profile(&local_settings_path, &local_config)?;
+        log::info!("Created local settings at: {}", local_settings_path.display());
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn router_port_accepts_numbers() {
+        assert_eq!(router_port(&json!({ "PORT": 8080 })), "8080");
+    }
+
+    #[test]
+    fn router_port_accepts_strings() {
+        assert_eq!(router_port(&json!({ "PORT": "8080" })), "8080");
+    }
+
+    #[test]
+    fn router_port_defaults_when_missing() {
+        assert_eq!(router_port(&json!({})), DEFAULT_ROUTER_PORT);
+    }
+
+    #[test]
+    fn router_port_must_be_valid() {
+        assert_eq!(parse_router_port(&json!({ "PORT": "3456" })).unwrap(), 3456);
+        assert_eq!(parse_router_port(&json!({ "PORT": 443 })).unwrap(), 443);
+        assert!(parse_router_port(&json!({ "PORT": 70000 })).is_err());
+        assert!(parse_router_port(&json!({ "PORT": "0" })).is_err());
+        assert!(parse_router_port(&json!({ "PORT": "abc" })).is_err());
+        assert!(parse_router_port(&json!({ "PORT": 80.5 })).is_err());
+        assert!(build_launch_env(&json!({ "PORT": "99999" }), &ConfigType::CodeRouter, HashMap::new(), EnvMode::Overlay).is_err());
+    }
+
+    #[test]
+    fn diffs_profiles_key_by_key() {
+        let old = json!({ "PORT": 3456, "env": { "A": "1", "B": "2" }, "gone": true });
+        let new = json!({ "PORT": 3456, "env": { "A": "1", "B": "3", "C": "4" } });
+        assert_eq!(diff_profiles(&old, &new), vec![
+            ProfileChange::Changed("env.B".to_string(), json!("2"), json!("3")),
+            ProfileChange::Added("env.C".to_string(), json!("4")),
+            ProfileChange::Removed("gone".to_string(), json!(true)),
+        ]);
+        assert!(diff_profiles(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn extends_merges_base_under_profile() {
+        let dir = std::env::temp_dir().join(format!("ccd-extends-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("base-settings.json"), r#"{ "env": { "A": "1", "B": "1" }, "model": "x" }"#).unwrap();
+        fs::write(dir.join("mid-settings.yaml"), "extends: base\nenv:\n  B: \"2\"\n").unwrap();
+        fs::write(dir.join("top-settings.json"), r#"{ "extends": "mid", "env": { "C": "3" } }"#).unwrap();
+        assert_eq!(
+            read_profile(&dir.join("top-settings.json")).unwrap(),
+            json!({ "env": { "A": "1", "B": "2", "C": "3" }, "model": "x" })
+        );
+        assert_eq!(read_profile_raw(&dir.join("top-settings.json")).unwrap()["extends"], "mid");
+
+        fs::write(dir.join("base-settings.json"), r#"{ "extends": "top" }"#).unwrap();
+        let error = read_profile(&dir.join("top-settings.json")).unwrap_err();
+        assert!(error.to_string().contains("cycle"), "{}", error);
+
+        fs::write(dir.join("a-config.json"), r#"{ "extends": "missing-ccr" }"#).unwrap();
+        assert!(cached_profile(&dir.join("a-config.json")).unwrap_err().contains("missing-config"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn router_base_url_uses_numeric_port() {
+        assert_eq!(router_base_url(&json!({ "PORT": 8080 })).unwrap(), "http://127.0.0.1:8080");
+    }
+
+    #[test]
+    fn router_base_url_checks_host() {
+        assert_eq!(router_base_url(&json!({ "HOST": "example.com", "SCHEME": "https" })).unwrap(), "https://example.com:3456");
+        assert!(router_base_url(&json!({ "HOST": "example.com:8080" })).is_err());
+        assert!(router_base_url(&json!({ "HOST": "a b" })).is_err());
+        assert_eq!(router_base_url(&json!({ "HOST": "::1" })).unwrap(), "http://[::1]:3456");
+        assert_eq!(router_base_url(&json!({ "HOST": "[::1]", "PORT": 8080 })).unwrap(), "http://[::1]:8080");
+        assert!(router_base_url(&json!({ "HOST": "[example.com]" })).is_err());
+    }
+
+    #[test]
+    fn profile_path_maps_names_to_files() {
+        let dirs = ConfigDirs {
+            claude: PathBuf::from("/c"),
+            router: PathBuf::from("/r"),
+            xdg_claude: None,
+            xdg_router: None,
+        };
+        assert_eq!(profile_path(&dirs, ConfigType::Claude, "work/prod").unwrap(), PathBuf::from("/c/work/prod-settings.json"));
+        assert_eq!(profile_path(&dirs, ConfigType::CodeRouter, "gem-ccr").unwrap(), PathBuf::from("/r/gem-config.json"));
+        assert!(profile_path(&dirs, ConfigType::Claude, "../escape").is_err());
+        assert!(profile_path(&dirs, ConfigType::Claude, "a/b/c").is_err());
+    }
+
+    #[test]
+    fn profile_suffix_accepts_supported_extensions() {
+        assert_eq!(strip_profile_suffix("work-settings.json", "-settings"), Some("work"));
+        assert_eq!(strip_profile_suffix("work-settings.YML", "-settings"), Some("work"));
+        assert_eq!(strip_profile_suffix("gem-config.toml", "-config"), Some("gem"));
+        assert_eq!(strip_profile_suffix("work-settings.json.bak", "-settings"), None);
+        assert_eq!(strip_profile_suffix("config.json", "-config"), None);
+    }
+
+    #[test]
+    fn parses_yaml_and_toml_profiles() {
+        let yaml = parse_profile_str(Path::new("a-settings.yaml"), "env:\n  MAX_TOKENS: 4096\n").unwrap();
+        assert_eq!(yaml, json!({ "env": { "MAX_TOKENS": 4096 } }));
+        let toml = parse_profile_str(Path::new("a-config.toml"), "PORT = 8080\nAPIKEY = \"k\"\n").unwrap();
+        assert_eq!(toml, json!({ "PORT": 8080, "APIKEY": "k" }));
+    }
+
+    #[test]
+    fn check_profile_flags_bad_router_values() {
+        assert!(check_profile(&json!({ "PORT": 3456, "APIKEY": "k" }), ConfigType::CodeRouter).is_empty());
+        assert!(check_profile(&json!({}), ConfigType::CodeRouter).is_empty());
+        assert_eq!(check_profile(&json!({ "PORT": "70000" }), ConfigType::CodeRouter).len(), 1);
+        assert_eq!(check_profile(&json!({ "PORT": "abc", "APIKEY": "" }), ConfigType::CodeRouter).len(), 2);
+    }
+
+    #[test]
+    fn check_profile_flags_bad_claude_env() {
+        assert!(check_profile(&json!({ "env": { "A": "x", "B": 1, "C": true } }), ConfigType::Claude).is_empty());
+        assert_eq!(check_profile(&json!({ "env": "x" }), ConfigType::Claude), vec!["env is not an object"]);
+        assert_eq!(check_profile(&json!({ "env": { "A": [1] } }), ConfigType::Claude), vec!["env.A is not a string"]);
+    }
+
+    #[test]
+    fn launch_env_applies_router_profile_env_last() {
+        let config = json!({ "PORT": 3456, "env": { "ANTHROPIC_BASE_URL": "https://proxy.example", "EXTRA": "1" } });
+        let env = build_launch_env(&config, &ConfigType::CodeRouter, HashMap::new(), EnvMode::Overlay).unwrap();
+        assert_eq!(env["ANTHROPIC_BASE_URL"], "https://proxy.example");
+        assert_eq!(env["EXTRA"], "1");
+        assert_eq!(env["ANTHROPIC_AUTH_TOKEN"], "test");
+    }
+
+    #[test]
+    fn removing_api_keys_keeps_key_order() {
+        let dir = std::env::temp_dir().join(format!("ccd-order-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let settings = r#"{
+  "model": "sonnet",
+  "env": {
+    "ZED": "1",
+    "ANTHROPIC_BASE_URL": "https://proxy.example",
+    "ALPHA": "2",
+    "ANTHROPIC_API_KEY": "sk-test",
+    "MIDDLE": "3"
+  },
+  "permissions": {
+    "deny": []
+  },
+  "apiKeyHelper": "helper"
+}"#;
+        fs::write(dir.join("settings.json"), settings).unwrap();
+        fs::write(dir.join("work-settings.json"), r#"{ "env": {} }"#).unwrap();
+
+        backup_settings_json_if_exists(&dir, &dir.join("work-settings.json"), &dir.join("backups"), 1).unwrap();
+        let written = fs::read_to_string(dir.join("settings.json")).unwrap();
+        let keys = ["\"model\"", "\"env\"", "\"ZED\"", "\"ALPHA\"", "\"MIDDLE\"", "\"permissions\"", "\"apiKeyHelper\""]
+            .map(|key| written.find(key).unwrap());
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]), "{}", written);
+        assert!(!written.contains("ANTHROPIC"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn masks_secrets_in_file_text() {
+        let text = "# router\nAPIKEY: abc\nabcdef: abc-1\nenv:\n  ANTHROPIC_AUTH_TOKEN: \"sk-0123456789\"\n";
+        let value = parse_profile_str(Path::new("a-config.yaml"), text).unwrap();
+        assert_eq!(
+            mask_secrets_in_text(text, &value),
+            "# router\nAPIKEY: ****\nabcdef: abc-1\nenv:\n  ANTHROPIC_AUTH_TOKEN: \"****6789\"\n"
+        );
+    }
+
+    #[test]
+    fn parse_errors_name_the_file() {
+        let path = std::env::temp_dir().join(format!("ccd-parse-error-{}-settings.json", std::process::id()));
+        fs::write(&path, "{\n  \"env\": {\n    \"A\": \n}").unwrap();
+        let error = format!("{:#}", read_profile(&path).unwrap_err());
+        assert!(error.starts_with(&format!("Failed to parse {}: ", path.display())), "{}", error);
+        assert!(error.contains("line 4"), "{}", error);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parses_bom_prefixed_crlf_file() {
+        let path = std::env::temp_dir().join(format!("ccd-bom-test-{}-settings.json", std::process::id()));
+        fs::write(&path, "\u{feff}{\r\n  \"env\": { \"A\": \"1\" }\r\n}\r\n").unwrap();
+        assert_eq!(read_profile(&path).unwrap(), json!({ "env": { "A": "1" } }));
+
+        write_profile(&path, &json!({ "env": { "A": "2" } })).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written.matches('\n').count(), written.matches("\r\n").count());
+        assert_eq!(read_profile(&path).unwrap(), json!({ "env": { "A": "2" } }));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_replaces_through_symlink() {
+        let dir = std::env::temp_dir().join(format!("ccd-atomic-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("profile.json");
+        fs::write(&target, "old").unwrap();
+
+        #[cfg(unix)]
+        {
+            let link = dir.join("config.json");
+            std::os::unix::fs::symlink(&target, &link).unwrap();
+            write_atomic(&link, b"new").unwrap();
+            assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+        }
+        #[cfg(not(unix))]
+        write_atomic(&target, b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), if cfg!(unix) { 2 } else { 1 });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_write_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("ccd-mode-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let existing = dir.join("config.json");
+        fs::write(&existing, "old").unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomic(&existing, b"new").unwrap();
+        assert_eq!(mode(&existing), 0o640);
+
+        let created = dir.join("settings.json");
+        write_atomic(&created, b"new").unwrap();
+        assert_eq!(mode(&created), 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hooks_accept_string_or_array() {
+        assert_eq!(launch_hooks(&json!({ "preLaunch": "ssh -fN tunnel" }), "preLaunch"), vec!["ssh -fN tunnel"]);
+        assert_eq!(launch_hooks(&json!({ "postLaunch": ["a", "b"] }), "postLaunch"), vec!["a", "b"]);
+        assert!(launch_hooks(&json!({ "preLaunch": 1 }), "preLaunch").is_empty());
+        assert_eq!(local_settings(&json!({ "preLaunch": "a", "env": {} })), None);
+    }
+
+    #[test]
+    fn rejects_directory_as_write_target() {
+        let dir = std::env::temp_dir().join(format!("ccd-target-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("config.json")).unwrap();
+        fs::write(dir.join("settings.json"), "{}").unwrap();
+
+        let error = ensure_replaceable(&dir.join("config.json"), true).unwrap_err();
+        assert!(error.to_string().contains("is a directory"));
+        assert!(ensure_replaceable(&dir.join("settings.json"), true).is_ok());
+        assert!(ensure_replaceable(&dir.join("missing.json"), true).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_skips_unreadable_profiles() {
+        let dir = std::env::temp_dir().join(format!("ccd-scan-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("ok-settings.json"), "{}").unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("dead-settings.json")).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("unrelated")).unwrap();
+
+        let mut skipped = 0;
+        let found = find_profile_files(&dir, "-settings", &mut skipped);
+        assert_eq!(found.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["ok"]);
+        assert_eq!(skipped, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_entries_of_profiles_file() {
+        let dir = std::env::temp_dir().join(format!("ccd-profiles-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(PROFILES_FILE),
+            r#"{ "base": { "env": { "A": "1", "B": "1" } }, "top": { "extends": "base", "env": { "B": "2" } }, "bad": 5 }"#,
+        )
+        .unwrap();
+        fs::write(dir.join("file-settings.json"), r#"{ "extends": "top" }"#).unwrap();
+
+        let top = dir.join("profiles.json#top");
+        assert_eq!(profile_entry(&top), Some((dir.join(PROFILES_FILE), "top")));
+        assert_eq!(profile_file(&top), dir.join(PROFILES_FILE));
+        assert_eq!(read_profile(&top).unwrap(), json!({ "env": { "A": "1", "B": "2" } }));
+        assert_eq!(cached_profile(&top).unwrap(), json!({ "env": { "A": "1", "B": "2" } }));
+        assert_eq!(read_profile(&dir.join("file-settings.json")).unwrap(), json!({ "env": { "A": "1", "B": "2" } }));
+        assert!(read_profile(&dir.join("profiles.json#bad")).unwrap_err().to_string().contains("is not an object"));
+        assert!(read_profile(&dir.join("profiles.json#missing")).is_err());
+
+        let mut skipped = 0;
+        let names = profiles_file_entries(&dir, &mut skipped).into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["base", "top", "bad"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recognizes_hidden_and_temp_files() {
+        for name in [".#work-settings.json", ".work-settings.json.swp", "work-settings.json.swp", "work-settings.json~",
+            "#work-settings.json#", "~$work-settings.json", ".work-settings.json.1234.tmp", "work-settings.json.bak"] {
+            assert!(is_hidden_or_temp_file(name), "{}", name);
+        }
+        for name in ["work-settings.json", "work-settings.yaml", "team", "my.team-config.toml"] {
+            assert!(!is_hidden_or_temp_file(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn launch_env_uses_router_api_key() {
+        let config = json!({ "APIKEY": "sk-router", "PORT": "3456" });
+        let env = build_launch_env(&config, &ConfigType::CodeRouter, HashMap::new(), EnvMode::Overlay).unwrap();
+        assert_eq!(env["ANTHROPIC_API_KEY"], "sk-router");
+        assert_eq!(env["ANTHROPIC_BASE_URL"], "http://127.0.0.1:3456");
+        assert!(!env.contains_key("ANTHROPIC_AUTH_TOKEN"));
+    }
+
+    #[test]
+    fn launch_env_resolves_key_indirection() {
+        std::env::set_var("CCD_TEST_ROUTER_KEY", "sk-from-env");
+        let config = json!({ "APIKEY": "${env:CCD_TEST_ROUTER_KEY}", "env": { "X_TOKEN": "t-${env:CCD_TEST_ROUTER_KEY}" } });
+        let env = build_launch_env(&config, &ConfigType::CodeRouter, HashMap::new(), EnvMode::Overlay).unwrap();
+        assert_eq!(env["ANTHROPIC_API_KEY"], "sk-from-env");
+        assert_eq!(env["X_TOKEN"], "t-sk-from-env");
+
+        let config = json!({ "APIKEY": "${env:CCD_TEST_UNSET_KEY}" });
+        let error = build_launch_env(&config, &ConfigType::CodeRouter, HashMap::new(), EnvMode::Overlay).unwrap_err();
+        assert!(error.to_string().contains("CCD_TEST_UNSET_KEY is not set"));
+
+        let dir = std::env::temp_dir().join(format!("ccd-key-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("key"), "sk-from-file\n").unwrap();
+        let config = json!({ "apiKeyFile": dir.join("key") });
+        let env = build_launch_env(&config, &ConfigType::Claude, HashMap::new(), EnvMode::Overlay).unwrap();
+        assert_eq!(env["ANTHROPIC_API_KEY"], "sk-from-file");
+        assert!(build_launch_env(&json!({ "apiKeyFile": dir.join("missing") }), &ConfigType::CodeRouter, HashMap::new(), EnvMode::Overlay).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn launch_env_falls_back_to_placeholder_token() {
+        let env = build_launch_env(&json!({}), &ConfigType::CodeRouter, HashMap::new(), EnvMode::Overlay).unwrap();
+        assert_eq!(env["ANTHROPIC_AUTH_TOKEN"], "test");
+        assert!(!env.contains_key("ANTHROPIC_API_KEY"));
+    }
+
+    #[test]
+    fn launch_env_stringifies_scalar_env_values() {
+        let config = json!({ "env": { "MAX_TOKENS": 4096, "DISABLE_TELEMETRY": true, "RATIO": 0.5 } });
+        let env = build_launch_env(&config, &ConfigType::Claude, HashMap::new(), EnvMode::Overlay).unwrap();
+        assert_eq!(env["MAX_TOKENS"], "4096");
+        assert_eq!(env["DISABLE_TELEMETRY"], "true");
+        assert_eq!(env["RATIO"], "0.5");
+    }
+
+    #[test]
+    fn launch_env_passes_nested_env_values_as_json() {
+        let config = json!({ "env": { "LIST": [1, 2], "UNSET": null } });
+        let env = build_launch_env(&config, &ConfigType::Claude, HashMap::new(), EnvMode::Overlay).unwrap();
+        assert_eq!(env["LIST"], "[1,2]");
+        assert!(!env.contains_key("UNSET"));
+    }
+
+    #[test]
+    fn launch_env_underlay_keeps_inherited_values() {
+        let config = json!({ "env": { "ANTHROPIC_MODEL": "profile", "EXTRA": "1" } });
+        let base = HashMap::from([("ANTHROPIC_MODEL".to_string(), "shell".to_string())]);
+        let env = build_launch_env(&config, &ConfigType::Claude, base.clone(), EnvMode::Underlay).unwrap();
+        assert_eq!(env["ANTHROPIC_MODEL"], "shell");
+        assert_eq!(env["EXTRA"], "1");
+
+        let env = build_launch_env(&config, &ConfigType::Claude, base, EnvMode::Overlay).unwrap();
+        assert_eq!(env["ANTHROPIC_MODEL"], "profile");
+    }
+
+    #[test]
+    fn strips_inherited_anthropic_vars_the_profile_does_not_set() {
+        let mut base = HashMap::from([
+            ("ANTHROPIC_BASE_URL".to_string(), "http://stale".to_string()),
+            ("ANTHROPIC_MODEL".to_string(), "shell".to_string()),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+        ]);
+        strip_inherited_anthropic_vars(&mut base, &json!({ "env": { "ANTHROPIC_MODEL": "profile" } }));
+        assert!(!base.contains_key("ANTHROPIC_BASE_URL"));
+        assert_eq!(base["ANTHROPIC_MODEL"], "shell");
+        assert_eq!(base["PATH"], "/usr/bin");
+    }
+
+    #[test]
+    fn launch_env_accepts_numeric_port() {
+        let base = HashMap::from([("PATH".to_string(), "/usr/bin".to_string())]);
+        let env = build_launch_env(&json!({ "PORT": 8080 }), &ConfigType::CodeRouter, base, EnvMode::Overlay).unwrap();
+        assert_eq!(env["ANTHROPIC_BASE_URL"], "http://127.0.0.1:8080");
+        assert_eq!(env["PATH"], "/usr/bin");
+    }
+}
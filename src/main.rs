@@ -1,13 +1,180 @@
 use anyhow::Result;
-use clap::{Arg, Command};
+use clap::{Arg, ArgMatches, Command};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::CompleteEnv;
+use std::{io::Write, sync::atomic::{AtomicBool, Ordering}, time::Duration};
 
+// Set from --quiet before anything else runs
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// Progress messages such as "Switched to ..."; --quiet drops them, errors still reach stderr
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !crate::QUIET.load(std::sync::atomic::Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod backup;
+mod cache;
 mod config;
+mod state;
 mod ui;
 mod commands;
+mod watch;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let matches = Command::new("claude-codust")
+    // Answers the shell's completion requests (COMPLETE=bash etc.) and exits; otherwise a no-op
+    CompleteEnv::with_factory(cli).complete();
+
+    let matches = cli().get_matches();
+
+    init_logging(&matches);
+    let mut options = switch_options(&matches)?;
+
+    if let Some(name) = matches.get_one::<String>("print_path") {
+        return commands::print_profile_path(&options.dirs, name);
+    }
+
+    if let Some(config_path) = matches.get_one::<String>("config") {
+        let exit_code = commands::launch_with_config_path(config_path, &options).await?;
+        exit_with(exit_code);
+        return Ok(());
+    }
+
+    // Claude's exit code, so scripts can tell whether the session succeeded
+    let mut exit_code = 0;
+    match matches.subcommand() {
+        Some(("code", sub_matches)) => {
+            options.claude_args = sub_matches
+                .get_many::<String>("claude_args")
+                .map(|args| args.cloned().collect())
+                .unwrap_or_default();
+            options.force = sub_matches.get_flag("force");
+            options.watch = sub_matches.get_flag("watch");
+
+            if sub_matches.get_flag("last") {
+                let config = commands::last_configuration(&options.dirs)?;
+                exit_code = commands::run_configuration(&config, &options).await?;
+            } else if let Some(name) = sub_matches.get_one::<String>("name") {
+                exit_code = commands::launch_by_name(name, &options).await?;
+            } else {
+                // Only looked up when it would be used, since a missing default is reported
+                let default = if sub_matches.get_flag("pick") {
+                    None
+                } else {
+                    commands::default_configuration(&options.dirs)
+                };
+                exit_code = match default {
+                    Some(config) => commands::run_configuration(&config, &options).await?,
+                    None => ui::show_interactive_selector(&options).await?,
+                };
+            }
+        }
+        Some(("switch", sub_matches)) => {
+            options.force = sub_matches.get_flag("force");
+            options.launch = false;
+
+            if let Some(name) = sub_matches.get_one::<String>("name") {
+                exit_code = commands::launch_by_name(name, &options).await?;
+            } else {
+                exit_code = ui::show_interactive_selector(&options).await?;
+            }
+        }
+        Some(("default", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").map(|s| s.as_str());
+            commands::default_profile(&options.dirs, name, sub_matches.get_flag("clear"))?;
+        }
+        Some(("list", sub_matches)) => {
+            commands::list_configurations(&options.dirs, sub_matches.get_flag("json"), options.sort, options.type_filter)?;
+        }
+        Some(("current", sub_matches)) => {
+            commands::print_current(&options.dirs, sub_matches.get_flag("json"))?;
+        }
+        Some(("new", sub_matches)) => {
+            commands::create_profile(&options.dirs, sub_matches.get_flag("ccr"), sub_matches.get_flag("force"))?;
+        }
+        Some(("pin", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            commands::pin_profile(&options.dirs, name, true)?;
+        }
+        Some(("unpin", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            commands::pin_profile(&options.dirs, name, false)?;
+        }
+        Some(("delete", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            commands::delete_profile(&options.dirs, name, sub_matches.get_flag("yes"), sub_matches.get_flag("force"))?;
+        }
+        Some(("duplicate", sub_matches)) => {
+            let source = sub_matches.get_one::<String>("source").unwrap();
+            let destination = sub_matches.get_one::<String>("destination").unwrap();
+            commands::duplicate_profile(&options.dirs, source, destination)?;
+        }
+        Some(("rename", sub_matches)) => {
+            let old = sub_matches.get_one::<String>("old").unwrap();
+            let new = sub_matches.get_one::<String>("new").unwrap();
+            commands::rename_profile(&options.dirs, old, new)?;
+        }
+        Some(("import", sub_matches)) => {
+            let file = sub_matches.get_one::<String>("file").unwrap();
+            let name = sub_matches.get_one::<String>("name").map(|s| s.as_str());
+            commands::import_profile(&options.dirs, file, name, options.type_filter, sub_matches.get_flag("force"))?;
+        }
+        Some(("export", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            let to = sub_matches.get_one::<String>("to").unwrap();
+            commands::export_profile(&options.dirs, name, to, sub_matches.get_flag("redact"), sub_matches.get_flag("force"))?;
+        }
+        Some(("restore", _)) => {
+            commands::restore_backup(&options.dirs, &options.backup_dir, options.alt_screen)?;
+        }
+        Some(("doctor", _)) => {
+            commands::doctor(&options.dirs, &options.ccr_bin)?;
+        }
+        Some(("which", _)) => {
+            commands::print_command_paths(&options.ccr_bin)?;
+        }
+        Some(("validate", _)) => {
+            commands::validate_profiles(&options.dirs, options.type_filter)?;
+        }
+        Some(("edit", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            commands::edit_profile(&options.dirs, name)?;
+        }
+        Some(("describe", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            let description = sub_matches.get_one::<String>("description").unwrap();
+            commands::describe_profile(&options.dirs, name, description)?;
+        }
+        Some(("diff", sub_matches)) => {
+            let first = sub_matches.get_one::<String>("first").unwrap();
+            let second = sub_matches.get_one::<String>("second").unwrap();
+            commands::diff_configurations(&options.dirs, first, second, options.show_secrets, options.color)?;
+        }
+        Some(("show", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            commands::show_profile(&options.dirs, name, sub_matches.get_flag("raw"), options.show_secrets, options.color)?;
+        }
+        Some(("path", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            commands::print_profile_path(&options.dirs, name)?;
+        }
+        Some(("env", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("name").unwrap();
+            commands::print_profile_env(&options.dirs, name, options.show_secrets, sub_matches.get_flag("export"))?;
+        }
+        _ => exit_code = ui::show_interactive_selector(&options).await?,
+    }
+
+    exit_with(exit_code);
+    Ok(())
+}
+
+fn cli() -> Command {
+    Command::new("claude-codust")
         .version(env!("CARGO_PKG_VERSION"))
         .about("Claude Code configuration switcher")
         .arg(
@@ -18,13 +185,675 @@ async fn main() -> Result<()> {
                 .value_name("FILE")
                 .action(clap::ArgAction::Set),
         )
-        .get_matches();
+        .arg(
+            Arg::new("print_path")
+                .long("print-path")
+                .help("Print the file path of a configuration and exit (same as the path command)")
+                .value_name("NAME")
+                .conflicts_with("config")
+                .add(ArgValueCandidates::new(complete_profile_names))
+                .action(clap::ArgAction::Set),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Log file operations, environment variables and spawned commands to stderr")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only print errors, no progress messages")
+                .conflicts_with("verbose")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("claude_dir")
+                .long("claude-dir")
+                .help("Directory containing Claude configurations (overrides CCD_CLAUDE_DIR and CLAUDE_CONFIG_DIR)")
+                .value_name("DIR")
+                .global(true),
+        )
+        .arg(
+            Arg::new("router_dir")
+                .long("router-dir")
+                .help("Directory containing Claude Code Router configurations (overrides CCD_ROUTER_DIR)")
+                .value_name("DIR")
+                .global(true),
+        )
+        .arg(
+            Arg::new("link")
+                .long("link")
+                .help("Symlink CodeRouter configurations into place instead of copying them")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .help("Print the actions that would be taken without executing them")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("confirm")
+                .long("confirm")
+                .help("Ask for confirmation in the selector before switching and launching")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("keep_local")
+                .long("keep-local")
+                .help("Keep .claude/settings.local.json after the Claude session ends")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_hooks")
+                .long("no-hooks")
+                .help("Skip the preLaunch/postLaunch commands of the profile")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_wait")
+                .long("no-wait")
+                .help("Exit as soon as claude exits instead of waiting for a key press")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("show_secrets")
+                .long("show-secrets")
+                .help("Show API keys and tokens instead of masking them")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_altscreen")
+                .long("no-altscreen")
+                .help("Draw the selector inline instead of on the alternate screen")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_color")
+                .long("no-color")
+                .help("Disable colored output (also honors NO_COLOR)")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("backup_count")
+                .long("backup-count")
+                .help("Number of settings.json backups to keep, at least 1 (default: 5)")
+                .value_name("N")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .global(true),
+        )
+        .arg(
+            Arg::new("backup_dir")
+                .long("backup-dir")
+                .help("Directory for settings.json backups (default: ~/.claude/backups, or CCD_BACKUP_DIR)")
+                .value_name("DIR")
+                .global(true),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("Order configurations by name, by type then name, or by last modified")
+                .value_name("ORDER")
+                .value_parser(["name", "type", "mtime"])
+                .default_value("type")
+                .global(true),
+        )
+        .arg(
+            Arg::new("type")
+                .long("type")
+                .help("Only show Claude or only CodeRouter configurations")
+                .value_name("TYPE")
+                .value_parser(["claude", "ccr"])
+                .global(true),
+        )
+        .arg(
+            Arg::new("env_mode")
+                .long("env-mode")
+                .help("overlay: profile variables override inherited ones; underlay: inherited ones win")
+                .value_name("MODE")
+                .value_parser(["overlay", "underlay"])
+                .default_value("overlay")
+                .global(true),
+        )
+        .arg(
+            Arg::new("keep_anthropic_env")
+                .long("keep-anthropic-env")
+                .help("Pass exported ANTHROPIC_* variables through even if a Claude profile doesn't set them")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_ccr_restart")
+                .long("no-ccr-restart")
+                .help("Don't run ccr restart when switching to a CodeRouter configuration")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("never_stop_ccr")
+                .long("never-stop-ccr")
+                .visible_alias("no-ccr-stop")
+                .help("Leave CCR running after claude exits, even if this run started it")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("always_stop_ccr")
+                .long("always-stop-ccr")
+                .help("Stop CCR after claude exits, even if it was already running before the switch")
+                .conflicts_with("never_stop_ccr")
+                .global(true)
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ccr_bin")
+                .long("ccr-bin")
+                .help("Name or path of the CodeRouter command (default: ccr, or CCD_CCR_BIN)")
+                .value_name("CMD")
+                .global(true),
+        )
+        .arg(
+            Arg::new("launch_cmd")
+                .long("launch-cmd")
+                .help("Run this command line instead of claude, with the same environment (e.g. env)")
+                .value_name("CMD")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ccr_timeout")
+                .long("ccr-timeout")
+                .help("Seconds to wait for ccr restart/stop before giving up (default: 30, or CCD_CCR_TIMEOUT)")
+                .value_name("SECS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .global(true),
+        )
+        .subcommand(
+            Command::new("code")
+                .about("Launch Claude with a configuration, or pick one interactively")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to switch to without showing the selector")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names)),
+                )
+                .arg(
+                    Arg::new("pick")
+                        .long("pick")
+                        .help("Show the selector even if a default configuration is set")
+                        .conflicts_with("name")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("last")
+                        .long("last")
+                        .help("Launch the last used configuration without showing the selector")
+                        .conflicts_with_all(["name", "pick"])
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Switch to a CodeRouter configuration even if its port is used by another process or config.json has local edits")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("watch")
+                        .long("watch")
+                        .help("Stop and relaunch claude with the configuration reapplied whenever its file changes")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("claude_args")
+                        .help("Extra arguments passed through to claude (after --)")
+                        .value_name("CLAUDE_ARGS")
+                        .num_args(0..)
+                        .last(true)
+                        .allow_hyphen_values(true),
+                ),
+        )
+        .subcommand(
+            Command::new("switch")
+                .about("Activate a configuration without launching Claude")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to activate without showing the selector")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names)),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Switch to a CodeRouter configuration even if its port is used by another process or config.json has local edits")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("default")
+                .about("Set the configuration `code` launches when no name is given")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name; without it the current default is printed")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names)),
+                )
+                .arg(
+                    Arg::new("clear")
+                        .long("clear")
+                        .help("Remove the default so `code` shows the selector again")
+                        .conflicts_with("name")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List available configurations without opening the selector")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print configurations as JSON")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("current")
+                .about("Print the name of the active configuration, e.g. for a shell prompt")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the active configuration as JSON (null when none is active)")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("new")
+                .about("Create a new configuration interactively")
+                .arg(
+                    Arg::new("ccr")
+                        .long("ccr")
+                        .help("Create a Claude Code Router configuration instead of a Claude one")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Overwrite an existing configuration with the same name")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("pin")
+                .about("Keep a configuration at the top of the selector and list")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to pin")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("unpin")
+                .about("Return a pinned configuration to its normal place")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to unpin")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("delete")
+                .about("Delete a configuration file")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to delete")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .short('y')
+                        .help("Skip the confirmation prompt")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Allow deleting the currently active configuration")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("duplicate")
+                .about("Copy a configuration to a new name of the same type")
+                .arg(
+                    Arg::new("source")
+                        .help("Configuration name to copy")
+                        .value_name("SRC")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("destination")
+                        .help("Name of the new configuration")
+                        .value_name("DST")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("rename")
+                .about("Rename a configuration, keeping its type")
+                .arg(
+                    Arg::new("old")
+                        .help("Current configuration name")
+                        .value_name("OLD")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("new")
+                        .help("New configuration name")
+                        .value_name("NEW")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Copy a configuration file from anywhere into the right directory")
+                .arg(
+                    Arg::new("file")
+                        .help("Configuration file to import")
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .help("Name of the imported configuration (default: derived from the file name)")
+                        .value_name("NAME"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Overwrite an existing configuration with the same name")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Copy a configuration file to another location")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to export")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("Destination file")
+                        .value_name("PATH")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("redact")
+                        .long("redact")
+                        .help("Mask API keys and tokens in the exported file")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .help("Overwrite the destination if it exists")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Restore settings.json from a previous backup"),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check the setup and report anything that would stop switching"),
+        )
+        .subcommand(
+            Command::new("which")
+                .about("Print where claude and ccr are run from"),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check every configuration for missing or malformed keys"),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("Open a configuration in $EDITOR and check it is still valid JSON")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to edit")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("describe")
+                .about("Set or clear the description shown next to a configuration")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to describe")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("description")
+                        .help("Description text; an empty string removes it")
+                        .value_name("TEXT")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show the keys that differ between two configurations")
+                .arg(
+                    Arg::new("first")
+                        .help("Configuration to compare from")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("second")
+                        .help("Configuration to compare to")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Print the contents of a configuration with secrets masked")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to show")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .help("Print the file as written instead of the parsed and merged JSON")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("path")
+                .about("Print the absolute path of a configuration file")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to print the path of")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("env")
+                .about("Print the environment variables a configuration would set")
+                .arg(
+                    Arg::new("name")
+                        .help("Configuration name to print the environment for")
+                        .value_name("NAME")
+                        .add(ArgValueCandidates::new(complete_profile_names))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("export")
+                        .long("export")
+                        .help("Prefix each line with `export` for use in a shell")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+}
 
-    if let Some(config_path) = matches.get_one::<String>("config") {
-        commands::launch_with_config_path(config_path).await?;
-    } else {
-        ui::show_interactive_selector().await?;
+// Only scans file names, no parsing, so completion stays instant with many profiles. Uses the
+// CCD_*_DIR variables but not --claude-dir/--router-dir, which aren't parsed at this point.
+fn complete_profile_names() -> Vec<CompletionCandidate> {
+    config::ConfigDirs::resolve(None, None)
+        .map(|dirs| config::profile_names(&dirs))
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+// Everything that restores the terminal has run by now, so exiting directly is safe
+fn exit_with(code: i32) {
+    if code != 0 {
+        std::process::exit(code);
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+// Warnings are always shown; --verbose adds info and debug lines. Everything goes to stderr so
+// `list --json` and `env` output stay clean.
+fn init_logging(matches: &ArgMatches) {
+    let sub_matches = matches.subcommand().map(|(_, m)| m).unwrap_or(matches);
+    let level = if sub_matches.get_flag("verbose") {
+        log::LevelFilter::Debug
+    } else if sub_matches.get_flag("quiet") {
+        QUIET.store(true, Ordering::Relaxed);
+        log::LevelFilter::Error
+    } else {
+        log::LevelFilter::Warn
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        // The trailing \r keeps lines aligned while the terminal is in raw mode
+        .format(|buf, record| writeln!(buf, "[{} {}] {}\r", buf.timestamp_millis(), record.level(), record.args()))
+        .target(env_logger::Target::Stderr)
+        .init();
+}
+
+fn switch_options(matches: &ArgMatches) -> Result<commands::SwitchOptions> {
+    // Global flags may be given before or after the subcommand
+    let sub_matches = matches.subcommand().map(|(_, m)| m).unwrap_or(matches);
+
+    let dirs = config::ConfigDirs::resolve(
+        sub_matches.get_one::<String>("claude_dir").map(String::as_str),
+        sub_matches.get_one::<String>("router_dir").map(String::as_str),
+    )?;
+
+    let backup_dir = match sub_matches
+        .get_one::<String>("backup_dir")
+        .cloned()
+        .or_else(|| std::env::var("CCD_BACKUP_DIR").ok().filter(|v| !v.trim().is_empty()))
+    {
+        Some(dir) => config::expand_path(&dir)?,
+        None => backup::default_backup_dir(&dirs.claude),
+    };
+
+    // Held to the same rules as --ccr-timeout rather than quietly replaced by the default
+    let ccr_timeout = match sub_matches.get_one::<u64>("ccr_timeout") {
+        Some(secs) => Some(*secs),
+        None => match std::env::var("CCD_CCR_TIMEOUT") {
+            Ok(value) if !value.trim().is_empty() => Some(
+                value.trim().parse::<u64>().ok().filter(|secs| *secs > 0).ok_or_else(|| {
+                    anyhow::anyhow!("Invalid CCD_CCR_TIMEOUT '{}', expected a number of seconds greater than 0", value)
+                })?,
+            ),
+            _ => None,
+        },
+    };
+
+    Ok(commands::SwitchOptions {
+        dirs,
+        link: sub_matches.get_flag("link"),
+        dry_run: sub_matches.get_flag("dry_run"),
+        confirm: sub_matches.get_flag("confirm"),
+        keep_local: sub_matches.get_flag("keep_local"),
+        wait: !sub_matches.get_flag("no_wait"),
+        hooks: !sub_matches.get_flag("no_hooks"),
+        launch: true,
+        watch: false,
+        reload: None,
+        show_secrets: sub_matches.get_flag("show_secrets"),
+        alt_screen: !sub_matches.get_flag("no_altscreen"),
+        // https://no-color.org: any non-empty NO_COLOR disables color
+        color: !sub_matches.get_flag("no_color")
+            && std::env::var("NO_COLOR").map(|v| v.is_empty()).unwrap_or(true),
+        backup_count: sub_matches
+            .get_one::<usize>("backup_count")
+            .copied()
+            .unwrap_or(backup::DEFAULT_BACKUP_COUNT),
+        backup_dir,
+        ccr_timeout: ccr_timeout.map(Duration::from_secs).unwrap_or(commands::DEFAULT_CCR_TIMEOUT),
+        ccr_bin: sub_matches
+            .get_one::<String>("ccr_bin")
+            .cloned()
+            .or_else(|| std::env::var("CCD_CCR_BIN").ok().filter(|v| !v.trim().is_empty()))
+            .unwrap_or_else(|| commands::DEFAULT_CCR_BIN.to_string()),
+        ccr_restart: !sub_matches.get_flag("no_ccr_restart"),
+        ccr_stop: if sub_matches.get_flag("always_stop_ccr") {
+            commands::CcrStop::Always
+        } else if sub_matches.get_flag("never_stop_ccr") {
+            commands::CcrStop::Never
+        } else {
+            commands::CcrStop::IfStarted
+        },
+        launch_cmd: sub_matches.get_one::<String>("launch_cmd").cloned(),
+        sort: sub_matches
+            .get_one::<String>("sort")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default(),
+        force: false,
+        env_mode: sub_matches
+            .get_one::<String>("env_mode")
+            .and_then(|m| m.parse().ok())
+            .unwrap_or_default(),
+        keep_anthropic_env: sub_matches.get_flag("keep_anthropic_env"),
+        type_filter: sub_matches
+            .get_one::<String>("type")
+            .and_then(|t| t.parse().ok()),
+        claude_args: Vec::new(),
+    })
+}
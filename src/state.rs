@@ -0,0 +1,103 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs, path::PathBuf};
+
+use crate::config::ConfigDirs;
+
+// Persisted between runs in ~/.claude/.ccd_state.json
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<String>,
+    // SHA-256 of what the last switch wrote to the router's config.json, to detect hand edits
+    // since then. Only the digest is kept so its keys aren't copied into this file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub router_digest: Option<String>,
+    // The profile that write came from; other profiles may have identical contents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub router_profile: Option<String>,
+    // Launched by `code` without a name instead of showing the selector
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    // Where the selector's cursor was when it last closed, whether or not anything was launched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected: Option<String>,
+    // Names listed above the other profiles
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pinned: Vec<String>,
+}
+
+impl State {
+    // A missing or unreadable state file just means nothing has been remembered yet
+    pub fn load(dirs: &ConfigDirs) -> State {
+        fs::read_to_string(state_path(dirs))
+            .ok()
+            .and_then(|content| serde_json::from_str(crate::config::strip_bom(&content)).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, dirs: &ConfigDirs) -> Result<()> {
+        let path = state_path(dirs);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        crate::config::write_atomic(&path, serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+// Keys are sorted first, so only a change in content gives a different digest
+pub fn config_digest(config: &serde_json::Value) -> String {
+    let mut config = config.clone();
+    config.sort_all_objects();
+    Sha256::digest(config.to_string().as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn state_path(dirs: &ConfigDirs) -> PathBuf {
+    dirs.claude.join(".ccd_state.json")
+}
+
+pub fn record_last_used(dirs: &ConfigDirs, name: &str) {
+    let mut state = State::load(dirs);
+    state.last_used = Some(name.to_string());
+    if let Err(e) = state.save(dirs) {
+        log::warn!("Could not save state: {}", e);
+    }
+}
+
+pub fn record_selected(dirs: &ConfigDirs, name: &str) {
+    let mut state = State::load(dirs);
+    state.selected = Some(name.to_string());
+    if let Err(e) = state.save(dirs) {
+        log::warn!("Could not save state: {}", e);
+    }
+}
+
+pub fn record_router_config(dirs: &ConfigDirs, name: &str, config: Option<&serde_json::Value>) {
+    let mut state = State::load(dirs);
+    state.router_digest = config.map(config_digest);
+    state.router_profile = Some(name.to_string());
+    if let Err(e) = state.save(dirs) {
+        log::warn!("Could not save state: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn digest_ignores_key_order_and_hides_values() {
+        let digest = config_digest(&json!({ "APIKEY": "sk-secret", "PORT": 3456 }));
+        assert_eq!(digest, config_digest(&json!({ "PORT": 3456, "APIKEY": "sk-secret" })));
+        assert_ne!(digest, config_digest(&json!({ "APIKEY": "sk-other", "PORT": 3456 })));
+        assert_eq!(digest.len(), 64);
+        assert!(!digest.contains("sk-secret"));
+    }
+}
@@ -1,95 +1,741 @@
 use anyhow::Result;
 use crossterm::{
-    cursor::Hide,
-    event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
-    execute,
+    cursor::{Hide, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Stylize,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use std::fmt::Write as _;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use unicode_width::UnicodeWidthStr;
 
-use crate::config::{ConfigItem};
-use crate::commands::switch_configuration;
+use crate::config::{mask_secrets, ConfigItem, ConfigType};
+use crate::commands::{run_configuration, SwitchOptions};
 
-pub async fn show_interactive_selector() -> Result<()> {
-    let configs = crate::config::load_configurations()?;
+// Returns claude's exit code, or 0 when nothing was launched
+pub async fn show_interactive_selector(options: &SwitchOptions) -> Result<i32> {
+    let (configs, skipped) = crate::config::load_configurations_counting_skipped(&options.dirs, options.sort)?;
     
     if configs.is_empty() {
-        println!("No configuration files found in ~/.claude/ or ~/.claude-code-router/");
-        return Ok(());
+        println!("{}", crate::config::no_configurations_hint(&options.dirs));
+        return Ok(0);
     }
 
-    enable_raw_mode()?;
-    execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+    let mut terminal = TerminalGuard::enter(options.alt_screen)?;
 
-    let result = run_selector(&configs).await;
+    let state = crate::state::State::load(&options.dirs);
+    // Reopen where the cursor was left, even if that run was cancelled
+    let cursor = state.selected.as_deref().or(state.last_used.as_deref());
+    let exit_code = run_selector(&mut terminal, &configs, state.last_used.as_deref(), cursor, skipped, options).await?;
 
-    execute!(io::stdout(), crossterm::cursor::Show, LeaveAlternateScreen)?;
+    // Printed on the normal screen once raw mode is off, so nothing is left behind on quit
+    drop(terminal);
+    match exit_code {
+        Some(code) => Ok(code),
+        None => {
+            println!("Cancelled");
+            Ok(0)
+        }
+    }
+}
+
+// Whether the alternate screen is on. Leaving it a second time makes some terminals (tmux)
+// jump back to the cursor position saved on entry, over whatever was printed since.
+static IN_ALT_SCREEN: AtomicBool = AtomicBool::new(false);
+
+// Puts the terminal back to normal line output if the selector left it in raw mode or on the
+// alternate screen. Safe to call any number of times, and when no selector ran at all.
+pub fn restore_terminal() -> Result<()> {
+    if IN_ALT_SCREEN.swap(false, Ordering::Relaxed) {
+        execute!(io::stdout(), Show, LeaveAlternateScreen)?;
+    } else {
+        execute!(io::stdout(), Show)?;
+    }
     disable_raw_mode()?;
+    Ok(())
+}
 
-    result
+// Raw mode and the alternate screen are undone on drop, so an error or panic anywhere in
+// the selector (including inside switch_configuration) never leaves the terminal garbled.
+// Code that restores the terminal early goes through restore_terminal, which drop uses too.
+// Without the alternate screen frames are drawn inline below the cursor and erased on drop.
+struct TerminalGuard {
+    alt_screen: bool,
+    // Terminal rows taken by the last inline frame, to get back to its first line
+    rows: usize,
 }
 
-async fn run_selector(configs: &[ConfigItem]) -> Result<()> {
-    let mut selected = 0;
+impl TerminalGuard {
+    fn enter(alt_screen: bool) -> Result<TerminalGuard> {
+        enable_raw_mode()?;
+        let guard = TerminalGuard { alt_screen, rows: 0 };
+        if alt_screen {
+            execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+            IN_ALT_SCREEN.store(true, Ordering::Relaxed);
+        } else {
+            execute!(io::stdout(), Hide)?;
+        }
+        Ok(guard)
+    }
+
+    // Sent to the terminal in a single write. Lines are overwritten in place and cleared up to
+    // their end instead of blanking the screen first, which is what made redraws flicker.
+    fn draw(&mut self, frame: &str) -> Result<()> {
+        let mut buffer = Vec::new();
+        self.queue_home(&mut buffer)?;
+        let width = crossterm::terminal::size().map(|(w, _)| w as usize).unwrap_or(80).max(1);
+        self.rows = 0;
+        for line in frame.split_terminator("\r\n") {
+            buffer.extend_from_slice(line.as_bytes());
+            queue!(buffer, crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine))?;
+            buffer.extend_from_slice(b"\r\n");
+            self.rows += visible_width(line).div_ceil(width).max(1);
+        }
+        queue!(buffer, crossterm::terminal::Clear(crossterm::terminal::ClearType::FromCursorDown))?;
+
+        let mut stdout = io::stdout();
+        stdout.write_all(&buffer)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    // Erases an inline frame so whatever is printed next starts where the selector did
+    fn clear(&mut self) -> Result<()> {
+        if !self.alt_screen {
+            let mut buffer = Vec::new();
+            self.queue_home(&mut buffer)?;
+            queue!(buffer, crossterm::terminal::Clear(crossterm::terminal::ClearType::FromCursorDown))?;
+            self.rows = 0;
+            let mut stdout = io::stdout();
+            stdout.write_all(&buffer)?;
+            stdout.flush()?;
+        }
+        Ok(())
+    }
+
+    fn queue_home(&self, buffer: &mut Vec<u8>) -> Result<()> {
+        if self.alt_screen {
+            queue!(buffer, crossterm::cursor::MoveTo(0, 0))?;
+        } else if self.rows > 0 {
+            queue!(buffer, crossterm::cursor::MoveToPreviousLine(self.rows as u16))?;
+        } else {
+            queue!(buffer, crossterm::cursor::MoveToColumn(0))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        if !self.alt_screen {
+            let _ = self.clear();
+        }
+        let _ = restore_terminal();
+    }
+}
+
+// None when the user quit without choosing anything
+async fn run_selector(
+    terminal: &mut TerminalGuard,
+    configs: &[ConfigItem],
+    last_used: Option<&str>,
+    cursor: Option<&str>,
+    skipped: usize,
+    options: &SwitchOptions,
+) -> Result<Option<i32>> {
+    let mut state = SelectorState {
+        filter: Filter {
+            query: String::new(),
+            config_type: options.type_filter,
+        },
+        selected: 0,
+        show_help: false,
+    };
+    // Start on the remembered config, or the first one if it no longer exists or is filtered out
+    state.selected = cursor
+        .and_then(|name| filter_configs(configs, &state.filter).iter().position(|c| c.name == name))
+        .unwrap_or(0);
+    let notes = [
+        (skipped > 0).then(|| format!("{} file(s) or folder(s) could not be read and are not listed", skipped)),
+        crate::config::unmatched_router_config(&options.dirs, configs),
+    ];
+    let mut message = Some(notes.into_iter().flatten().collect::<Vec<_>>().join("; ")).filter(|m| !m.is_empty());
+    let mut scroll = 0;
+    // What is on screen; keys that change nothing render the same frame and cause no redraw
+    let mut drawn = String::new();
 
     loop {
-        print_selector_ui(configs, selected)?;
+        let filtered = filter_configs(configs, &state.filter);
+        let frame = if state.show_help {
+            render_help_overlay()?
+        } else {
+            render_selector_ui(&filtered, state.selected, &mut scroll, &state.filter, last_used, message.as_deref(), options)?
+        };
+        if frame != drawn {
+            terminal.draw(&frame)?;
+            drawn = frame;
+        }
 
+        let event = event::read()?;
+        if let Event::Resize(..) = event {
+            drawn.clear();
+        }
         if let Event::Key(KeyEvent {
             code,
+            modifiers,
             kind: KeyEventKind::Press,
             ..
-        }) = event::read()?
+        }) = event
         {
-            match code {
-                KeyCode::Up => {
-                    if selected == 0 {
-                        selected = configs.len() - 1;
-                    } else {
-                        selected -= 1;
-                    }
+            message = None;
+            let chosen = match state.handle_key(code, modifiers, filtered.len()) {
+                KeyAction::Quit => {
+                    remember_selection(filtered.get(state.selected), options);
+                    return Ok(None);
                 }
-                KeyCode::Down => {
-                    if selected == configs.len() - 1 {
-                        selected = 0;
-                    } else {
-                        selected += 1;
+                KeyAction::Choose(i) => filtered.get(i),
+                KeyAction::OpenFolder(i) => {
+                    if let Some(config) = filtered.get(i) {
+                        message = Some(open_folder(&config.path));
                     }
+                    None
                 }
-                KeyCode::Enter => {
-                    switch_configuration(&configs[selected]).await?;
-                    return Ok(());
-                }
-                KeyCode::Esc | KeyCode::Char('q') => {
-                    println!("\r\nCancelled");
-                    return Ok(());
+                KeyAction::Stay => None,
+            };
+
+            // Out-of-range numbers are ignored; broken configs show their error instead of launching
+            if let Some(config) = chosen {
+                match &config.error {
+                    Some(error) => message = Some(format!("Cannot switch to {}: {}", config.name, error)),
+                    None if options.confirm && !confirm_launch(terminal, config, options)? => {
+                        message = Some(format!("Did not switch to {}", config.name));
+                        drawn.clear();
+                    }
+                    None => {
+                        remember_selection(Some(config), options);
+                        // What the switch prints has to land on the normal screen, or it is
+                        // gone as soon as the alternate screen is left
+                        terminal.clear()?;
+                        restore_terminal()?;
+                        return run_configuration(config, options).await.map(Some);
+                    }
                 }
+            }
+        }
+    }
+}
+
+// What a key press asks of the selector once its own state is updated
+#[derive(Debug, PartialEq)]
+enum KeyAction {
+    Stay,
+    Quit,
+    Choose(usize),
+    OpenFolder(usize),
+}
+
+struct SelectorState {
+    filter: Filter,
+    selected: usize,
+    show_help: bool,
+}
+
+impl SelectorState {
+    // `len` is the length of the filtered list. When it is empty there is nothing to move to or
+    // choose, so only the filter, the help overlay and quitting respond.
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers, len: usize) -> KeyAction {
+        let empty = len == 0;
+        match code {
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return KeyAction::Quit,
+            // The overlay swallows every other key so the list underneath keeps its state
+            KeyCode::Char('?') | KeyCode::Esc if self.show_help => self.show_help = false,
+            _ if self.show_help => {}
+            KeyCode::Char('?') => self.show_help = true,
+            KeyCode::Up | KeyCode::Down | KeyCode::Enter if empty => {}
+            KeyCode::Up => self.selected = move_up(self.selected, len),
+            KeyCode::Down => self.selected = move_down(self.selected, len),
+            // Vim-style navigation only applies while no filter is being typed
+            KeyCode::Char('j' | 'k' | 'g' | 'G' | 'o' | '1'..='9') if empty && self.filter.query.is_empty() => {}
+            KeyCode::Char('k') if self.filter.query.is_empty() => self.selected = move_up(self.selected, len),
+            KeyCode::Char('j') if self.filter.query.is_empty() => self.selected = move_down(self.selected, len),
+            KeyCode::Char('g') if self.filter.query.is_empty() => self.selected = 0,
+            KeyCode::Char('G') if self.filter.query.is_empty() => self.selected = len - 1,
+            KeyCode::Char('o') if self.filter.query.is_empty() => return KeyAction::OpenFolder(self.selected),
+            KeyCode::Tab => {
+                // Cycles all -> claude -> ccr
+                self.filter.config_type = match self.filter.config_type {
+                    None => Some(ConfigType::Claude),
+                    Some(ConfigType::Claude) => Some(ConfigType::CodeRouter),
+                    Some(ConfigType::CodeRouter) => None,
+                };
+                self.selected = 0;
+            }
+            KeyCode::Enter => return KeyAction::Choose(self.selected),
+            KeyCode::Char(c @ '1'..='9') if self.filter.query.is_empty() => {
+                return KeyAction::Choose(c as usize - '1' as usize);
+            }
+            KeyCode::Esc if !self.filter.query.is_empty() => {
+                self.filter.query.clear();
+                self.selected = 0;
+            }
+            KeyCode::Esc | KeyCode::Char('q') if self.filter.query.is_empty() => return KeyAction::Quit,
+            KeyCode::Backspace if !self.filter.query.is_empty() => {
+                self.filter.query.pop();
+                self.selected = 0;
+            }
+            KeyCode::Char(c) => {
+                self.filter.query.push(c);
+                self.selected = 0;
+            }
+            _ => {}
+        }
+        KeyAction::Stay
+    }
+}
+
+// Opens the folder holding `path` in the file manager without waiting for it; returns the
+// message to show, which includes the path when there is no opener to run
+fn open_folder(path: &std::path::Path) -> String {
+    let folder = path.parent().unwrap_or(path);
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(target_os = "windows") {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let spawned = std::process::Command::new(opener)
+        .arg(folder)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    match spawned {
+        Ok(mut child) => {
+            // Reaped in the background so the selector stays responsive
+            std::thread::spawn(move || child.wait());
+            format!("Opened {}", folder.display())
+        }
+        Err(e) => {
+            log::debug!("Could not run {}: {}", opener, e);
+            format!("Could not open a file manager ({} not available), the folder is {}", opener, folder.display())
+        }
+    }
+}
+
+fn remember_selection(config: Option<&&ConfigItem>, options: &SwitchOptions) {
+    if let Some(config) = config {
+        crate::state::record_selected(&options.dirs, &config.name);
+    }
+}
+
+const HELP_LINES: [&str; 15] = [
+    "Keyboard shortcuts",
+    "",
+    "  Up/Down, j/k   move the selection",
+    "  g / G          jump to the first / last configuration",
+    "  Enter          switch to the selected configuration",
+    "  1-9            switch to the numbered row",
+    "  Tab            cycle all / claude / ccr configurations",
+    "  o              open the folder of the selected configuration",
+    "  any text       filter by name",
+    "  Backspace      edit the filter",
+    "  Esc            clear the filter, or quit when it is empty",
+    "  q, Ctrl-C      quit",
+    "  ?              show or hide this help",
+    "",
+    "j, k, g, G, o, q and digits are part of the filter while one is typed. Press ? or Esc to return.",
+];
+
+// Replaces the list until dismissed; lines are cut to the terminal so nothing wraps or scrolls
+fn render_help_overlay() -> Result<String> {
+    let mut frame = String::new();
+    let (width, height) = crossterm::terminal::size().map(|(w, h)| (w as usize, h as usize)).unwrap_or((80, 24));
+    for line in HELP_LINES.iter().take(height.saturating_sub(1)) {
+        write!(frame, "{}\r\n", truncate_to_width(line, width.saturating_sub(1)))?;
+    }
+    Ok(frame)
+}
+
+// Display width with color escape sequences left out, for counting wrapped rows
+fn visible_width(line: &str) -> usize {
+    let mut width = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a byte in @..~
+            chars.by_ref().skip(1).find(|c| ('@'..='~').contains(c));
+        } else {
+            width += unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+        }
+    }
+    width
+}
+
+// Cuts by display width so wide characters never push a line past the terminal edge
+fn truncate_to_width(line: &str, width: usize) -> String {
+    let mut used = 0;
+    line.chars()
+        .take_while(|c| {
+            used += unicode_width::UnicodeWidthChar::width(*c).unwrap_or(0);
+            used <= width
+        })
+        .collect()
+}
+
+// Shows what selecting `config` will change and waits for y/N; any other key goes back to the list
+fn confirm_launch(terminal: &mut TerminalGuard, config: &ConfigItem, options: &SwitchOptions) -> Result<bool> {
+    let mut frame = String::new();
+
+    write!(frame, "Switch to {}{}?\r\n", config.name, config.config_type.get_indicator())?;
+    write!(frame, "\r\n")?;
+    write!(frame, "  Profile: {}\r\n", config.path.display())?;
+    match config.config_type {
+        ConfigType::Claude => {
+            write!(frame, "  Target:  {} (ANTHROPIC_* keys removed)\r\n", options.dirs.claude.join("settings.json").display())?;
+        }
+        ConfigType::CodeRouter => {
+            let target = options.dirs.router.join("config.json");
+            let action = if options.link { "symlinked" } else { "overwritten" };
+            write!(frame, "  Target:  {} ({})\r\n", target.display(), action)?;
+            let unchanged = crate::config::read_json_file(&target).is_some()
+                && crate::config::read_json_file(&target) == crate::config::read_json_file(&config.path);
+            let restart = if !options.ccr_restart {
+                "no (--no-ccr-restart)"
+            } else if unchanged {
+                "only if ccr is not running"
+            } else {
+                "yes"
+            };
+            write!(frame, "  CCR restart: {}\r\n", restart)?;
+        }
+    }
+    write!(frame, "\r\n")?;
+    write!(frame, "Continue? [y/N]\r\n")?;
+    terminal.draw(&frame)?;
+
+    loop {
+        if let Event::Key(KeyEvent { code, kind: KeyEventKind::Press, .. }) = event::read()? {
+            return Ok(matches!(code, KeyCode::Char('y') | KeyCode::Char('Y')));
+        }
+    }
+}
+
+// Minimal list picker used by commands that need a one-off choice, e.g. `restore`
+pub fn select_item(title: &str, items: &[String], alt_screen: bool) -> Result<Option<usize>> {
+    let mut terminal = TerminalGuard::enter(alt_screen)?;
+    run_item_selector(&mut terminal, title, items)
+}
+
+fn run_item_selector(terminal: &mut TerminalGuard, title: &str, items: &[String]) -> Result<Option<usize>> {
+    let mut selected = 0;
+
+    loop {
+        let mut frame = String::new();
+        write!(frame, "{}\r\n", title)?;
+        write!(frame, "Use Up/Down or j/k to navigate, Enter to select, Esc/q to quit\r\n")?;
+        write!(frame, "\r\n")?;
+        for (i, item) in items.iter().enumerate() {
+            let prefix = if i == selected { "> " } else { "  " };
+            write!(frame, "{}{}\r\n", prefix, item)?;
+        }
+        terminal.draw(&frame)?;
+
+        if let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event::read()?
+        {
+            match code {
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+                KeyCode::Up | KeyCode::Char('k') => selected = move_up(selected, items.len()),
+                KeyCode::Down | KeyCode::Char('j') => selected = move_down(selected, items.len()),
+                KeyCode::Enter if !items.is_empty() => return Ok(Some(selected)),
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(None),
                 _ => {}
             }
         }
     }
 }
 
-fn print_selector_ui(configs: &[ConfigItem], selected: usize) -> Result<()> {
-    execute!(io::stdout(), crossterm::cursor::MoveTo(0, 0))?;
-    execute!(io::stdout(), crossterm::terminal::Clear(crossterm::terminal::ClearType::FromCursorDown))?;
+fn move_up(selected: usize, len: usize) -> usize {
+    if len == 0 {
+        0
+    } else if selected == 0 {
+        len - 1
+    } else {
+        selected - 1
+    }
+}
+
+fn move_down(selected: usize, len: usize) -> usize {
+    if len == 0 || selected >= len - 1 {
+        0
+    } else {
+        selected + 1
+    }
+}
+
+// What the selector currently narrows the list by: typed text and the Tab-cycled type
+struct Filter {
+    query: String,
+    config_type: Option<ConfigType>,
+}
+
+fn filter_configs<'a>(configs: &'a [ConfigItem], filter: &Filter) -> Vec<&'a ConfigItem> {
+    let query = filter.query.to_lowercase();
+    configs
+        .iter()
+        .filter(|c| filter.config_type.is_none_or(|t| c.config_type == t))
+        .filter(|c| {
+            let label = format!("{}{}", c.name, c.config_type.get_indicator());
+            label.to_lowercase().contains(&query)
+        })
+        .collect()
+}
+
+// Only the selected config is read, so scrolling never touches the other files
+fn render_preview(config: &ConfigItem, show_secrets: bool) -> String {
+    crate::config::cached_profile(&config.path)
+        .ok()
+        .map(|value| if show_secrets { value } else { mask_secrets(&value) })
+        .and_then(|value| serde_json::to_string_pretty(&value).ok())
+        .unwrap_or_else(|| "(could not parse)".to_string())
+}
+
+// Coarse relative time such as "5m ago"; clock skew putting a file in the future counts as now
+fn format_age(modified: std::time::SystemTime, now: std::time::SystemTime) -> String {
+    let seconds = now.duration_since(modified).map(|d| d.as_secs()).unwrap_or(0);
+    match seconds {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", seconds / 60),
+        3600..86400 => format!("{}h ago", seconds / 3600),
+        86400..2592000 => format!("{}d ago", seconds / 86400),
+        2592000..31536000 => format!("{}mo ago", seconds / 2592000),
+        _ => format!("{}y ago", seconds / 31536000),
+    }
+}
+
+// Header lines printed above the list: title, help, filter and a blank line
+const HEADER_LINES: usize = 4;
+// Lines kept free below the list so the preview stays visible with long lists
+const PREVIEW_RESERVE: usize = 8;
+
+// Adjusts `scroll` so that `selected` is inside a window of `rows` entries
+fn scroll_window(scroll: &mut usize, selected: usize, rows: usize, len: usize) {
+    if selected < *scroll {
+        *scroll = selected;
+    } else if selected >= *scroll + rows {
+        *scroll = selected + 1 - rows;
+    }
+    *scroll = (*scroll).min(len.saturating_sub(rows));
+}
+
+fn render_selector_ui(
+    configs: &[&ConfigItem],
+    selected: usize,
+    scroll: &mut usize,
+    filter: &Filter,
+    last_used: Option<&str>,
+    message: Option<&str>,
+    options: &SwitchOptions,
+) -> Result<String> {
+    let mut frame = String::new();
+    let height = crossterm::terminal::size().map(|(_, h)| h as usize).unwrap_or(24);
 
-    print!("Claude Code Configuration Selector\r\n");
-    print!("Use Up/Down to navigate, Enter to select, Esc/q to quit\r\n");
-    print!("\r\n");
+    write!(frame, "Claude Code Configuration Selector\r\n")?;
+    write!(frame, "Use Up/Down or j/k to navigate, g/G for first/last, Enter or 1-9 to select, type to filter, Tab for type, o to open its folder, ? for help, Esc/q to quit\r\n")?;
+    let type_label = filter.config_type.map_or("all", |t| t.as_str());
+    write!(frame, "Filter: {}  [type: {}]\r\n", filter.query, type_label)?;
+    write!(frame, "\r\n")?;
 
+    if configs.is_empty() {
+        let nothing = if filter.query.is_empty() && filter.config_type.is_none() {
+            "  No configurations"
+        } else {
+            "  No configurations match the filter"
+        };
+        write!(frame, "{}\r\n", nothing)?;
+        write!(frame, "\r\n")?;
+        let hint = if filter.query.is_empty() { "Esc or q to quit" } else { "Backspace edits the filter, Esc clears it" };
+        write!(frame, "{}\r\n", message.unwrap_or(hint))?;
+        return Ok(frame);
+    }
+
+    // Display width, not byte length, so wide/CJK names still line up
     let max_name_len = configs.iter()
-        .map(|c| c.name.len() + c.config_type.get_indicator().len())
+        .map(|c| c.name.width() + c.config_type.get_indicator().width())
+        .max()
+        .unwrap_or(0);
+
+    // Descriptions get their own column, which disappears when no config has one
+    let max_description_len = configs.iter()
+        .map(|c| c.description.as_deref().map_or(0, |d| d.width() + 1))
         .max()
         .unwrap_or(0);
 
-    for (i, config) in configs.iter().enumerate() {
+    // Two extra lines are reserved for the "more" indicators
+    let rows = height
+        .saturating_sub(HEADER_LINES + 2 + PREVIEW_RESERVE)
+        .max(3)
+        .min(configs.len());
+    scroll_window(scroll, selected, rows, configs.len());
+    let mut lines_used = HEADER_LINES + rows;
+
+    if *scroll > 0 {
+        write!(frame, "  ↑ more\r\n")?;
+        lines_used += 1;
+    }
+
+    // The star column only takes space once something is pinned
+    let any_pinned = configs.iter().any(|c| c.pinned);
+
+    let now = std::time::SystemTime::now();
+    for (i, config) in configs.iter().enumerate().skip(*scroll).take(rows) {
         let prefix = if i == selected { "> " } else { "  " };
+        // Only the first nine rows can be picked with a number key
+        let number = if i < 9 { format!("{} ", i + 1) } else { "  ".to_string() };
+        let star = match (any_pinned, config.pinned) {
+            (false, _) => "",
+            (true, true) => "★ ",
+            (true, false) => "  ",
+        };
         let type_indicator = config.config_type.get_indicator();
         let name_with_indicator = format!("{}{}", config.name, type_indicator);
-        print!("{}{:<width$} {}\r\n", prefix, name_with_indicator, config.path.display(), width = max_name_len);
+        let active_marker = match (config.loaded, config.active) {
+            (true, _) => " (loaded into config.json)",
+            (false, true) => " (active)",
+            (false, false) => "",
+        };
+        let last_used_marker = if last_used == Some(config.name.as_str()) { " (last used)" } else { "" };
+        let broken_marker = if config.error.is_some() { " ⚠" } else { "" };
+        let padding = " ".repeat(max_name_len.saturating_sub(name_with_indicator.width()));
+        let description = config.description.as_deref()
+            .map(|d| format!(" {}", d))
+            .unwrap_or_default();
+        let description = format!("{}{}", description, " ".repeat(max_description_len - description.width()));
+        let head = format!("{}{}{}{}{}", prefix, number, star, name_with_indicator, padding);
+        let tail = format!(
+            " {}{}{}{}",
+            config.path.display(),
+            active_marker,
+            last_used_marker,
+            broken_marker
+        );
+        let age = config.modified
+            .map(|modified| format!(" {}", format_age(modified, now)))
+            .unwrap_or_default();
+
+        if options.color {
+            // StyledContent resets colors and attributes after each part, so nothing bleeds
+            let style = |part: String| {
+                let mut styled = part.stylize();
+                if matches!(config.config_type, ConfigType::CodeRouter) {
+                    styled = styled.cyan();
+                }
+                if i == selected {
+                    styled = styled.bold().reverse();
+                }
+                styled
+            };
+            write!(frame, "{}{}{}{}\r\n", style(head), style(description).dim(), style(tail), style(age).dim())?;
+        } else {
+            write!(frame, "{}{}{}{}\r\n", head, description, tail, age)?;
+        }
     }
 
-    io::stdout().flush()?;
-    Ok(())
-}
\ No newline at end of file
+    if *scroll + rows < configs.len() {
+        write!(frame, "  ↓ more\r\n")?;
+        lines_used += 1;
+    }
+
+    if let Some(message) = message {
+        write!(frame, "\r\n{}\r\n", message)?;
+        lines_used += 2;
+    }
+
+    if let Some(config) = configs.get(selected) {
+        // The preview gets whatever space is left and is cut off rather than scrolling the screen
+        let available = height.saturating_sub(lines_used + 2);
+        if available > 0 {
+            write!(frame, "\r\n")?;
+            write!(frame, "Preview: {}\r\n", config.name)?;
+            let preview = render_preview(config, options.show_secrets);
+            let lines = preview.lines().collect::<Vec<_>>();
+            for (i, line) in lines.iter().enumerate().take(available) {
+                if i + 1 == available && lines.len() > available {
+                    write!(frame, "  ...\r\n")?;
+                } else {
+                    write!(frame, "  {}\r\n", line)?;
+                }
+            }
+        }
+    }
+
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn formats_relative_age() {
+        let now = SystemTime::now();
+        assert_eq!(format_age(now, now), "just now");
+        assert_eq!(format_age(now - Duration::from_secs(2 * 3600 + 5), now), "2h ago");
+        assert_eq!(format_age(now - Duration::from_secs(3 * 86400), now), "3d ago");
+        assert_eq!(format_age(now + Duration::from_secs(60), now), "just now");
+    }
+
+    fn selector_state(query: &str) -> SelectorState {
+        SelectorState {
+            filter: Filter {
+                query: query.to_string(),
+                config_type: None,
+            },
+            selected: 0,
+            show_help: false,
+        }
+    }
+
+    #[test]
+    fn empty_list_ignores_navigation_and_enter() {
+        let none = KeyModifiers::NONE;
+        let mut state = selector_state("");
+        for code in [KeyCode::Up, KeyCode::Down, KeyCode::Enter, KeyCode::Char('j'), KeyCode::Char('G'), KeyCode::Char('o'), KeyCode::Char('1')] {
+            assert_eq!(state.handle_key(code, none, 0), KeyAction::Stay);
+            assert_eq!(state.selected, 0);
+        }
+        assert_eq!(state.handle_key(KeyCode::Char('q'), none, 0), KeyAction::Quit);
+        assert_eq!(state.handle_key(KeyCode::Esc, none, 0), KeyAction::Quit);
+
+        // A filter that matches nothing is edited or cleared before Esc quits
+        let mut state = selector_state("zz");
+        assert_eq!(state.handle_key(KeyCode::Enter, none, 0), KeyAction::Stay);
+        assert_eq!(state.handle_key(KeyCode::Down, none, 0), KeyAction::Stay);
+        assert_eq!(state.handle_key(KeyCode::Backspace, none, 0), KeyAction::Stay);
+        assert_eq!(state.filter.query, "z");
+        assert_eq!(state.handle_key(KeyCode::Esc, none, 0), KeyAction::Stay);
+        assert!(state.filter.query.is_empty());
+        assert_eq!(state.handle_key(KeyCode::Esc, none, 0), KeyAction::Quit);
+    }
+
+    #[test]
+    fn non_empty_list_moves_and_chooses() {
+        let none = KeyModifiers::NONE;
+        let mut state = selector_state("");
+        assert_eq!(state.handle_key(KeyCode::Up, none, 3), KeyAction::Stay);
+        assert_eq!(state.selected, 2);
+        assert_eq!(state.handle_key(KeyCode::Char('g'), none, 3), KeyAction::Stay);
+        assert_eq!(state.selected, 0);
+        assert_eq!(state.handle_key(KeyCode::Enter, none, 3), KeyAction::Choose(0));
+        assert_eq!(state.handle_key(KeyCode::Char('2'), none, 3), KeyAction::Choose(1));
+        assert_eq!(state.handle_key(KeyCode::Char('o'), none, 3), KeyAction::OpenFolder(0));
+    }
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use notify::{RecursiveMode, Watcher};
+use std::{fs, path::Path};
+use tokio::sync::watch;
+
+// Counts changes to one file for as long as it is kept alive
+pub struct FileWatcher {
+    _watcher: notify::RecommendedWatcher,
+    pub changes: watch::Receiver<u64>,
+}
+
+// Watches the parent directory rather than the file itself, since editors often save by
+// writing a new file and renaming it over the old one, which a watch on the file would miss
+pub fn watch_file(path: &Path) -> Result<FileWatcher> {
+    let path = fs::canonicalize(path)?;
+    let parent = path.parent().ok_or_else(|| anyhow::anyhow!("Cannot watch {}", path.display()))?;
+    let file_name = path.file_name().map(|n| n.to_os_string());
+
+    let (sender, changes) = watch::channel(0);
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+        Ok(event) if !event.kind.is_access() && event.paths.iter().any(|p| p.file_name() == file_name.as_deref()) => {
+            sender.send_modify(|count| *count += 1);
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("Watching for changes failed: {}", e),
+    })?;
+    watcher.watch(parent, RecursiveMode::NonRecursive)?;
+    log::debug!("Watching {}", path.display());
+
+    Ok(FileWatcher { _watcher: watcher, changes })
+}
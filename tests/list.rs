@@ -0,0 +1,383 @@
+// Runs the built binary against throwaway config directories, pointed to by CCD_CLAUDE_DIR and
+// CCD_ROUTER_DIR, so nothing under the real home directory is read or written
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+struct TestHome {
+    root: PathBuf,
+}
+
+impl TestHome {
+    fn new(name: &str) -> TestHome {
+        let root = std::env::temp_dir().join(format!("ccd-it-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("claude")).unwrap();
+        fs::create_dir_all(root.join("router")).unwrap();
+        TestHome { root }
+    }
+
+    fn claude_dir(&self) -> PathBuf {
+        self.root.join("claude")
+    }
+
+    fn router_dir(&self) -> PathBuf {
+        self.root.join("router")
+    }
+
+    fn write(&self, dir: &Path, file_name: &str, content: &str) {
+        fs::write(dir.join(file_name), content).unwrap();
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_claude-codust"));
+        command
+            .args(args)
+            .current_dir(&self.root)
+            .env("HOME", &self.root)
+            .env("USERPROFILE", &self.root)
+            .env("CCD_CLAUDE_DIR", self.claude_dir())
+            .env("CCD_ROUTER_DIR", self.router_dir())
+            .env("XDG_CONFIG_HOME", self.root.join("xdg"))
+            .env("NO_COLOR", "1")
+            .env_remove("CLAUDE_CONFIG_DIR");
+        command
+    }
+
+    // `list --json`, as (name, type, has error) per configuration
+    fn list(&self, args: &[&str]) -> Vec<(String, String, bool)> {
+        let output = self.run(&[&["list", "--json"], args].concat());
+        assert!(output.status.success(), "list failed: {}", String::from_utf8_lossy(&output.stderr));
+        let configs: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+        configs
+            .iter()
+            .map(|c| {
+                (
+                    c["name"].as_str().unwrap().to_string(),
+                    c["type"].as_str().unwrap().to_string(),
+                    c.get("error").is_some(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl Drop for TestHome {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn entry(name: &str, config_type: &str, has_error: bool) -> (String, String, bool) {
+    (name.to_string(), config_type.to_string(), has_error)
+}
+
+#[test]
+fn lists_claude_then_router_profiles_by_name() {
+    let home = TestHome::new("sorted");
+    home.write(&home.claude_dir(), "zeta-settings.json", r#"{ "env": { "A": "1" } }"#);
+    home.write(&home.claude_dir(), "alpha-settings.yaml", "env:\n  A: \"2\"\n");
+    home.write(&home.router_dir(), "gemini-config.json", r#"{ "PORT": 3456 }"#);
+    home.write(&home.router_dir(), "openai-config.toml", "PORT = 8080\n");
+    // The live files and unrelated names are not profiles
+    home.write(&home.claude_dir(), "settings.json", "{}");
+    home.write(&home.router_dir(), "config.json", "{}");
+    home.write(&home.claude_dir(), "notes.txt", "");
+
+    assert_eq!(home.list(&[]), vec![
+        entry("alpha", "claude", false),
+        entry("zeta", "claude", false),
+        entry("gemini-ccr", "ccr", false),
+        entry("openai-ccr", "ccr", false),
+    ]);
+
+    let names = home.list(&["--sort", "name"]).into_iter().map(|(name, _, _)| name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["alpha", "gemini-ccr", "openai-ccr", "zeta"]);
+}
+
+#[test]
+fn names_subfolder_profiles_after_the_folder() {
+    let home = TestHome::new("subfolder");
+    fs::create_dir_all(home.claude_dir().join("work")).unwrap();
+    fs::create_dir_all(home.router_dir().join("team")).unwrap();
+    home.write(&home.claude_dir().join("work"), "prod-settings.json", "{}");
+    home.write(&home.router_dir().join("team"), "gemini-config.json", "{}");
+
+    assert_eq!(home.list(&[]), vec![entry("work/prod", "claude", false), entry("team/gemini-ccr", "ccr", false)]);
+}
+
+#[test]
+fn empty_and_missing_directories_list_nothing() {
+    let home = TestHome::new("empty");
+    assert!(home.list(&[]).is_empty());
+
+    fs::remove_dir_all(home.claude_dir()).unwrap();
+    fs::remove_dir_all(home.router_dir()).unwrap();
+    assert!(home.list(&[]).is_empty());
+}
+
+#[test]
+fn invalid_profiles_are_listed_with_their_error() {
+    let home = TestHome::new("invalid");
+    home.write(&home.claude_dir(), "good-settings.json", "{}");
+    home.write(&home.claude_dir(), "broken-settings.json", "{ not json");
+    home.write(&home.router_dir(), "broken-config.yaml", "PORT: [unclosed");
+
+    assert_eq!(home.list(&[]), vec![
+        entry("broken", "claude", true),
+        entry("good", "claude", false),
+        entry("broken-ccr", "ccr", true),
+    ]);
+}
+
+#[test]
+fn editor_temp_files_are_not_listed() {
+    let home = TestHome::new("temp-files");
+    fs::create_dir_all(home.claude_dir().join("team")).unwrap();
+    home.write(&home.claude_dir(), "work-settings.json", "{}");
+    home.write(&home.claude_dir(), ".work-settings.json.swp", "");
+    home.write(&home.claude_dir(), "work-settings.json.swp", "");
+    home.write(&home.claude_dir(), "work-settings.json~", "{}");
+    home.write(&home.claude_dir(), ".#work-settings.json", "{}");
+    home.write(&home.claude_dir(), "#work-settings.json#", "{}");
+    home.write(&home.claude_dir().join("team"), ".#prod-settings.json", "{}");
+    home.write(&home.router_dir(), "gemini-config.json", "{}");
+    home.write(&home.router_dir(), "gemini-config.json~", "{}");
+    home.write(&home.router_dir(), ".gemini-config.json.swp", "");
+
+    assert_eq!(home.list(&[]), vec![entry("work", "claude", false), entry("gemini-ccr", "ccr", false)]);
+}
+
+#[test]
+fn type_filter_limits_the_list() {
+    let home = TestHome::new("type-filter");
+    home.write(&home.claude_dir(), "work-settings.json", "{}");
+    home.write(&home.router_dir(), "gemini-config.json", "{}");
+
+    assert_eq!(home.list(&["--type", "ccr"]), vec![entry("gemini-ccr", "ccr", false)]);
+    assert_eq!(home.list(&["--type", "claude"]), vec![entry("work", "claude", false)]);
+}
+
+#[test]
+fn marks_the_profile_loaded_into_config_json() {
+    let home = TestHome::new("loaded");
+    // Two profiles with identical contents are both active, only the recorded one is loaded
+    home.write(&home.router_dir(), "a-config.json", r#"{ "PORT": 3456 }"#);
+    home.write(&home.router_dir(), "b-config.json", r#"{ "PORT": 3456 }"#);
+    home.write(&home.router_dir(), "config.json", r#"{ "PORT": 3456 }"#);
+    home.write(&home.claude_dir(), ".ccd_state.json", r#"{ "router_profile": "b-ccr" }"#);
+
+    let output = home.run(&["list", "--json"]);
+    let configs: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let loaded = configs.iter().map(|c| (c["active"].as_bool().unwrap(), c["loaded"].as_bool().unwrap())).collect::<Vec<_>>();
+    assert_eq!(loaded, vec![(true, false), (true, true)]);
+
+    // The marker follows the profile when it is renamed
+    assert!(home.run(&["rename", "b-ccr", "c"]).status.success());
+    let output = home.run(&["list", "--json"]);
+    let configs: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let loaded = configs.iter().map(|c| (c["name"].as_str().unwrap(), c["loaded"].as_bool().unwrap())).collect::<Vec<_>>();
+    assert_eq!(loaded, vec![("a-ccr", false), ("c-ccr", true)]);
+
+    // A hand-edited config.json matches nothing and `current` says so on stderr
+    home.write(&home.router_dir(), "config.json", r#"{ "PORT": 9999 }"#);
+    let output = home.run(&["current"]);
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("edited after c-ccr was loaded"));
+}
+
+#[test]
+fn claude_config_dir_is_used_unless_ccd_claude_dir_is_set() {
+    let home = TestHome::new("claude-config-dir");
+    let other = home.root.join("other");
+    fs::create_dir_all(&other).unwrap();
+    home.write(&other, "elsewhere-settings.json", "{}");
+    home.write(&home.claude_dir(), "here-settings.json", "{}");
+
+    let names = |output: Output| {
+        let configs: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+        configs.iter().map(|c| c["name"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+    };
+    let output = home.command(&["list", "--json"]).env("CLAUDE_CONFIG_DIR", &other).output().unwrap();
+    assert_eq!(names(output), vec!["here"]);
+    let output = home.command(&["list", "--json"]).env_remove("CCD_CLAUDE_DIR").env("CLAUDE_CONFIG_DIR", &other).output().unwrap();
+    assert_eq!(names(output), vec!["elsewhere"]);
+}
+
+#[test]
+fn profiles_file_entries_are_listed_next_to_profile_files() {
+    let home = TestHome::new("profiles-file");
+    home.write(&home.claude_dir(), "work-settings.json", "{}");
+    home.write(
+        &home.claude_dir(),
+        "profiles.json",
+        r#"{ "personal": { "env": {} }, "work": { "env": {} }, "broken": "not an object" }"#,
+    );
+
+    // The per-file profile wins a name clash
+    assert_eq!(home.list(&[]), vec![
+        entry("broken", "claude", true),
+        entry("personal", "claude", false),
+        entry("work", "claude", false),
+    ]);
+    let output = home.run(&["path", "personal"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), home.claude_dir().join("profiles.json").display().to_string());
+    let output = home.run(&["path", "work"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), home.claude_dir().join("work-settings.json").display().to_string());
+}
+
+#[test]
+fn edited_router_config_is_not_overwritten_without_a_terminal() {
+    let home = TestHome::new("router-edits");
+    home.write(&home.router_dir(), "a-config.json", r#"{ "PORT": 39151 }"#);
+    home.write(&home.router_dir(), "config.json", r#"{ "PORT": 39151, "edited": true }"#);
+    home.write(&home.claude_dir(), ".ccd_state.json", r#"{ "router_digest": "0" }"#);
+
+    let output = home
+        .command(&["switch", "a-ccr", "--no-ccr-restart", "--ccr-bin", "true"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--force"));
+    assert!(fs::read_to_string(home.router_dir().join("config.json")).unwrap().contains("edited"));
+}
+
+#[test]
+fn missing_profile_error_names_the_configured_directories() {
+    let home = TestHome::new("find-empty");
+
+    let output = home.run(&["path", "work"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&home.claude_dir().display().to_string()), "{}", stderr);
+    assert!(!stderr.contains("~/.claude/"), "{}", stderr);
+}
+
+#[test]
+fn duplicate_refuses_a_name_taken_in_another_format() {
+    let home = TestHome::new("duplicate-clash");
+    home.write(&home.claude_dir(), "staging-settings.json", "{}");
+    home.write(&home.claude_dir(), "work-settings.yaml", "env: {}");
+
+    let output = home.run(&["duplicate", "work", "staging"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    assert!(!home.claude_dir().join("staging-settings.yaml").exists());
+}
+
+#[test]
+fn import_refuses_a_name_taken_in_another_format() {
+    let home = TestHome::new("import-clash");
+    home.write(&home.claude_dir(), "work-settings.json", "{}");
+    home.write(&home.root, "work.yaml", "env: {}");
+
+    for args in [&["import", "work.yaml"][..], &["import", "work.yaml", "--force"]] {
+        let output = home.run(args);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    }
+    assert!(!home.claude_dir().join("work-settings.yaml").exists());
+
+    // Replacing the very same file is still allowed with --force
+    home.write(&home.root, "work.json", r#"{ "env": {} }"#);
+    assert!(!home.run(&["import", "work.json"]).status.success());
+    assert!(home.run(&["import", "work.json", "--force"]).status.success());
+}
+
+#[test]
+fn rename_refuses_a_name_taken_in_another_format() {
+    let home = TestHome::new("rename-clash");
+    home.write(&home.router_dir(), "fast-config.toml", "PORT = 3456");
+    home.write(&home.router_dir(), "slow-config.json", r#"{ "PORT": 3456 }"#);
+
+    let output = home.run(&["rename", "slow-ccr", "fast"]);
+    assert!(!output.status.success());
+    assert!(home.router_dir().join("slow-config.json").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn quiet_switch_hides_ccr_output() {
+    let home = TestHome::new("quiet-restart");
+    home.write(&home.router_dir(), "a-config.json", r#"{ "PORT": 39152 }"#);
+
+    let output = home.run(&["switch", "a-ccr", "--quiet", "--ccr-bin", "echo"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    let output = home.run(&["switch", "a-ccr", "--ccr-bin", "echo"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("restart"));
+}
+
+#[test]
+fn backup_count_of_zero_is_rejected() {
+    let home = TestHome::new("backup-count");
+
+    assert!(!home.run(&["list", "--backup-count", "0"]).status.success());
+    assert!(home.run(&["list", "--backup-count", "1"]).status.success());
+}
+
+// A port held by something else is only blamed on another process when `ccr status` answers
+#[cfg(unix)]
+#[test]
+fn busy_port_is_checked_with_ccr_status() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let home = TestHome::new("busy-port");
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    home.write(&home.router_dir(), "a-config.json", &format!(r#"{{ "PORT": {} }}"#, port));
+    let switch = |ccr: &str| home.run(&["switch", "a-ccr", "--no-ccr-restart", "--ccr-bin", ccr]);
+    let fake_ccr = |name: &str, status: &str| {
+        let path = home.root.join(name);
+        fs::write(&path, format!("#!/bin/sh\necho '{}'\n", status)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.display().to_string()
+    };
+
+    let output = switch(&home.root.join("missing-ccr").display().to_string());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = switch(&fake_ccr("stopped-ccr", "Status: Not Running"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already in use"));
+    assert!(switch(&fake_ccr("running-ccr", "Status: Running")).status.success());
+}
+
+#[test]
+fn new_rejects_hidden_names() {
+    use std::io::Write;
+
+    let home = TestHome::new("new-hidden");
+    let mut child = home
+        .command(&["new"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b".work\n\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid profile name"));
+    assert!(!home.claude_dir().join(".work-settings.json").exists());
+}
+
+#[test]
+fn invalid_ccr_timeout_is_rejected() {
+    let home = TestHome::new("ccr-timeout");
+
+    for value in ["abc", "0"] {
+        let output = home.command(&["list"]).env("CCD_CCR_TIMEOUT", value).output().unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("CCD_CCR_TIMEOUT"));
+    }
+    assert!(!home.run(&["list", "--ccr-timeout", "0"]).status.success());
+    assert!(home.command(&["list"]).env("CCD_CCR_TIMEOUT", "5").output().unwrap().status.success());
+}